@@ -0,0 +1,1098 @@
+// Command line parsing for minigrep
+// Every flag is described once in the FLAGS table below which drives both the parser and the
+// `--help` output, so the two can't drift apart.  Flags may appear anywhere on the command line,
+// short flags can be bundled (`-in`), values can be attached (`-A3`, `--include=*.rs`) and `--`
+// marks the end of the flags
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io::{self, Read};
+use std::path::PathBuf;
+
+use crate::settings::Settings;
+use crate::{
+    BinaryMode, ColorChoice, Config, ContextOptions, Field, GlobFilter, Matcher, MatcherOptions, Mode,
+    OutputOptions, Region, SortBy, WalkOptions,
+};
+
+// Everything that can go wrong while reading the command line
+// `Help` and `Version` aren't really errors but, like a usage error, they mean there is no search
+// to run; main prints them to stdout and exits successfully
+#[derive(Debug)]
+pub enum ArgsError {
+    Help,
+    Version,
+    UnknownFlag(String),
+    MissingValue(String),
+    InvalidValue { flag: String, value: String },
+    // Two flags that can't be used together
+    Conflict(&'static str, &'static str),
+    // A flag that only makes sense along with another one
+    Requires(&'static str, &'static str),
+    MissingQuery,
+    // `-f` named a file we couldn't read
+    PatternFile { path: String, error: io::Error },
+    InvalidPattern(regex::Error),
+    InvalidGlob(globset::Error),
+    // A configuration file we couldn't use, see settings.rs
+    Settings { path: PathBuf, message: String },
+    UnknownPreset(String),
+}
+
+impl fmt::Display for ArgsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ArgsError::Help => write!(f, "{}", help()),
+            ArgsError::Version => write!(f, "minigrep {}", env!("CARGO_PKG_VERSION")),
+            ArgsError::UnknownFlag(flag) => write!(f, "unknown flag '{}'", flag),
+            ArgsError::MissingValue(flag) => write!(f, "'{}' needs a value", flag),
+            ArgsError::InvalidValue { flag, value } => {
+                write!(f, "invalid value '{}' for '{}'", value, flag)
+            }
+            ArgsError::Conflict(a, b) => write!(f, "'{}' can't be used with '{}'", a, b),
+            ArgsError::Requires(a, b) => write!(f, "'{}' needs '{}'", a, b),
+            ArgsError::MissingQuery => write!(f, "didn't get a query string"),
+            ArgsError::PatternFile { path, error } => {
+                write!(f, "can't read patterns from '{}': {}", path, error)
+            }
+            ArgsError::InvalidPattern(e) => write!(f, "invalid regular expression: {}", e),
+            ArgsError::InvalidGlob(e) => write!(f, "invalid glob: {}", e),
+            ArgsError::Settings { path, message } => write!(f, "{}: {}", path.display(), message),
+            ArgsError::UnknownPreset(name) => write!(f, "no preset named '{}'", name),
+        }
+    }
+}
+
+impl Error for ArgsError {}
+
+#[derive(Clone, Copy)]
+enum Flag {
+    Pattern,
+    PatternFile,
+    IgnoreCase,
+    CaseSensitive,
+    Regex,
+    Word,
+    Multiline,
+    CodeOnly,
+    CommentsOnly,
+    Field,
+    CsvColumn,
+    Fuzzy,
+    Invert,
+    Count,
+    FilesWithMatches,
+    FilesWithoutMatch,
+    MaxCount,
+    Sort,
+    Unique,
+    LineNumber,
+    ByteOffset,
+    Column,
+    After,
+    Before,
+    Context,
+    Include,
+    Exclude,
+    Hidden,
+    NoIgnore,
+    Jobs,
+    Color,
+    Colors,
+    Json,
+    Text,
+    Binary,
+    Decompress,
+    NoIndex,
+    Watch,
+    Replace,
+    InPlace,
+    DryRun,
+    Preset,
+    NoConfig,
+    Help,
+    Version,
+}
+
+// What comes after a flag.  The string is the name the value is shown with in `--help`
+#[derive(Clone, Copy)]
+enum Takes {
+    Nothing,
+    Value(&'static str),
+    // A value that can only be given attached to the long flag, eg: `--fuzzy=2`
+    OptionalValue(&'static str),
+}
+
+struct Spec {
+    short: Option<char>,
+    long: &'static str,
+    takes: Takes,
+    help: &'static str,
+    flag: Flag,
+}
+
+const FLAGS: &[Spec] = &[
+    Spec { short: Some('e'), long: "regexp", takes: Takes::Value("PATTERN"), flag: Flag::Pattern,
+           help: "Search for PATTERN (repeatable)" },
+    Spec { short: Some('f'), long: "file", takes: Takes::Value("FILE"), flag: Flag::PatternFile,
+           help: "Search for every pattern in FILE, one per line" },
+    Spec { short: Some('i'), long: "ignore-case", takes: Takes::Nothing, flag: Flag::IgnoreCase,
+           help: "Search case insensitively" },
+    Spec { short: Some('s'), long: "case-sensitive", takes: Takes::Nothing,
+           flag: Flag::CaseSensitive,
+           help: "Search case sensitively, even if CASE_INSENSITIVE is set" },
+    Spec { short: None, long: "regex", takes: Takes::Nothing, flag: Flag::Regex,
+           help: "Treat the query as a regular expression" },
+    Spec { short: Some('w'), long: "word-regexp", takes: Takes::Nothing, flag: Flag::Word,
+           help: "Only match whole words" },
+    Spec { short: None, long: "fuzzy", takes: Takes::OptionalValue("K"), flag: Flag::Fuzzy,
+           help: "Match up to K (default 1) edits away from the query" },
+    Spec { short: Some('U'), long: "multiline", takes: Takes::Nothing, flag: Flag::Multiline,
+           help: "Let matches span lines, printing every line they touch" },
+    Spec { short: None, long: "code-only", takes: Takes::Nothing, flag: Flag::CodeOnly,
+           help: "Ignore matches in comments and string literals" },
+    Spec { short: None, long: "comments-only", takes: Takes::Nothing, flag: Flag::CommentsOnly,
+           help: "Only match inside comments" },
+    Spec { short: None, long: "field", takes: Takes::Value("NAME[=QUERY]"), flag: Flag::Field,
+           help: "Match against field NAME of each JSON Lines record" },
+    Spec { short: None, long: "csv-column", takes: Takes::Value("NUM"), flag: Flag::CsvColumn,
+           help: "Match against column NUM of each CSV record" },
+    Spec { short: Some('v'), long: "invert-match", takes: Takes::Nothing, flag: Flag::Invert,
+           help: "Print the lines that don't match" },
+    Spec { short: Some('c'), long: "count", takes: Takes::Nothing, flag: Flag::Count,
+           help: "Print only the number of matching lines per file" },
+    Spec { short: Some('l'), long: "files-with-matches", takes: Takes::Nothing,
+           flag: Flag::FilesWithMatches,
+           help: "Print only the names of files that match" },
+    Spec { short: Some('L'), long: "files-without-match", takes: Takes::Nothing,
+           flag: Flag::FilesWithoutMatch,
+           help: "Print only the names of files that don't match" },
+    Spec { short: Some('m'), long: "max-count", takes: Takes::Value("NUM"), flag: Flag::MaxCount,
+           help: "Stop reading a file after NUM matching lines (matches with -U)" },
+    Spec { short: None, long: "sort", takes: Takes::Value("BY"), flag: Flag::Sort,
+           help: "Search files in order of path, mtime or none (the default)" },
+    Spec { short: None, long: "unique", takes: Takes::Nothing, flag: Flag::Unique,
+           help: "Print each distinct matching line of a file once, with a count" },
+    Spec { short: Some('n'), long: "line-number", takes: Takes::Nothing, flag: Flag::LineNumber,
+           help: "Prefix each line with its line number" },
+    Spec { short: Some('b'), long: "byte-offset", takes: Takes::Nothing, flag: Flag::ByteOffset,
+           help: "Prefix each line with its byte offset" },
+    Spec { short: None, long: "column", takes: Takes::Nothing, flag: Flag::Column,
+           help: "Prefix each line with the column of the first match" },
+    Spec { short: Some('A'), long: "after-context", takes: Takes::Value("NUM"), flag: Flag::After,
+           help: "Print NUM lines after each match" },
+    Spec { short: Some('B'), long: "before-context", takes: Takes::Value("NUM"), flag: Flag::Before,
+           help: "Print NUM lines before each match" },
+    Spec { short: Some('C'), long: "context", takes: Takes::Value("NUM"), flag: Flag::Context,
+           help: "Print NUM lines before and after each match" },
+    Spec { short: None, long: "include", takes: Takes::Value("GLOB"), flag: Flag::Include,
+           help: "Only search files matching GLOB (repeatable)" },
+    Spec { short: None, long: "exclude", takes: Takes::Value("GLOB"), flag: Flag::Exclude,
+           help: "Skip files and directories matching GLOB (repeatable)" },
+    Spec { short: None, long: "hidden", takes: Takes::Nothing, flag: Flag::Hidden,
+           help: "Search hidden files and directories" },
+    Spec { short: None, long: "no-ignore", takes: Takes::Nothing, flag: Flag::NoIgnore,
+           help: "Don't respect .gitignore and .ignore files" },
+    Spec { short: Some('j'), long: "threads", takes: Takes::Value("NUM"), flag: Flag::Jobs,
+           help: "Search NUM files at once (default: one per CPU)" },
+    Spec { short: None, long: "color", takes: Takes::Value("WHEN"), flag: Flag::Color,
+           help: "Highlight matches: auto (only on a terminal), always or never" },
+    Spec { short: None, long: "colors", takes: Takes::Value("PART=STYLE"), flag: Flag::Colors,
+           help: "Set the color of path, number, separator or match (repeatable)" },
+    Spec { short: None, long: "json", takes: Takes::Nothing, flag: Flag::Json,
+           help: "Print results as JSON Lines records" },
+    Spec { short: Some('a'), long: "text", takes: Takes::Nothing, flag: Flag::Text,
+           help: "Search binary files as if they were text" },
+    Spec { short: None, long: "binary", takes: Takes::Nothing, flag: Flag::Binary,
+           help: "Report binary files that match, even inside directories" },
+    Spec { short: Some('z'), long: "search-zip", takes: Takes::Nothing, flag: Flag::Decompress,
+           help: "Search inside gzip, bzip2, xz and zstd compressed files" },
+    Spec { short: None, long: "no-index", takes: Takes::Nothing, flag: Flag::NoIndex,
+           help: "Search every file even if a directory has an index" },
+    Spec { short: None, long: "watch", takes: Takes::Nothing, flag: Flag::Watch,
+           help: "Keep going, printing new matches as files change" },
+    Spec { short: None, long: "replace", takes: Takes::Value("TEMPLATE"), flag: Flag::Replace,
+           help: "Print lines with each match replaced by TEMPLATE" },
+    Spec { short: None, long: "in-place", takes: Takes::Nothing, flag: Flag::InPlace,
+           help: "Write the replacements back to the files instead" },
+    Spec { short: None, long: "dry-run", takes: Takes::Nothing, flag: Flag::DryRun,
+           help: "Show what --in-place would change as a diff" },
+    Spec { short: None, long: "preset", takes: Takes::Value("NAME"), flag: Flag::Preset,
+           help: "Use the settings of a preset from a configuration file" },
+    Spec { short: None, long: "no-config", takes: Takes::Nothing, flag: Flag::NoConfig,
+           help: "Ignore configuration files" },
+    Spec { short: Some('h'), long: "help", takes: Takes::Nothing, flag: Flag::Help,
+           help: "Print this help and exit" },
+    Spec { short: Some('V'), long: "version", takes: Takes::Nothing, flag: Flag::Version,
+           help: "Print the version and exit" },
+];
+
+pub fn help() -> String {
+    let mut help = String::from("\
+Search for QUERY in each FILE and print the matching lines.
+
+Usage: minigrep [OPTIONS] QUERY [FILE]...
+       minigrep [OPTIONS] -e PATTERN... [-f FILE]... [FILE]...
+       minigrep index build [DIR]
+
+A FILE of `-`, or no FILE at all, reads standard input.  Directories are searched recursively.
+With -e or -f there is no QUERY argument and lines matching any of the patterns are printed.
+
+Options:
+");
+    for spec in FLAGS {
+        let short = spec.short.map_or("    ".to_string(), |c| format!("-{}, ", c));
+        let long = match spec.takes {
+            Takes::Nothing => format!("--{}", spec.long),
+            Takes::Value(value) => format!("--{} {}", spec.long, value),
+            Takes::OptionalValue(value) => format!("--{}[={}]", spec.long, value),
+        };
+        help.push_str(&format!("  {}{:<26}{}\n", short, long, spec.help));
+    }
+    help.push_str("
+Case sensitivity:
+  Searches are case sensitive unless the CASE_INSENSITIVE environment variable is set.
+  -i and -s always win over the environment; if both are given the last one wins.
+  Plain queries use full Unicode case folding, so -i finds STRASSE when searching for straße.
+
+Binary files:
+  A file with a NUL byte near its start is binary.  Binary files found in a directory are
+  skipped, and for those named on the command line \"Binary file FILE matches\" is printed
+  instead of their lines.  --binary reports matching binary files wherever they were found and
+  -a searches them like text.  Text that isn't valid UTF-8 is still searched, with invalid
+  bytes shown as U+FFFD.
+
+Fuzzy matching:
+  With --fuzzy a line matches if part of it is at most K single character insertions,
+  deletions or substitutions away from the query.  Each line shows how many edits it needed as
+  ~N before its text, and a file's lines are printed closest first.
+
+Indexing:
+  `minigrep index build DIR` saves which trigrams (runs of three bytes) each file under DIR
+  contains in DIR/.minigrep-index, defaulting to the current directory.  Searching DIR then
+  skips the files that can't contain a plain query.  Running it again only reads the files that
+  changed, and files changed since the last build are always searched.  To search for `index`
+  in a file named build instead, use `minigrep -- index build`.
+
+Multiline:
+  With -U the query is matched against whole files, so a regular expression like
+  'fn \\w+\\(\\s*self' finds a signature broken over two lines.  Every line a match touches is
+  printed.  ^ and $ still match at line boundaries and . doesn't match a line ending, but \\s
+  and [^x] do.
+
+Code and comments:
+  --code-only and --comments-only only select a line if a match in it starts in code (outside
+  comments and string literals) or in a comment.  They know Rust, C and C++, Python and
+  JavaScript files by their extension, and skip files in any other language.
+
+Structured logs:
+  With --field or --csv-column each line is a record, and the query is matched against one of
+  its fields rather than the whole line.  NAME may be a path into nested objects like
+  request.method, and --field NAME=QUERY also searches for QUERY like -e does, eg:
+  --field level=error.  CSV columns count from 1.  Lines that aren't valid records are
+  reported and skipped, and records without the field are never selected.
+
+Limiting and sorting:
+  -m stops reading a file once NUM lines were selected, after printing the context that follows
+  the last of them.  --sort path orders every file by its path and --sort mtime puts the least
+  recently modified first, instead of searching them in the order they were named in.
+  --unique prints each distinct selected line of a file once, where it was first seen, with
+  how many times it was selected as Nx before its text.
+
+Compressed files:
+  -z recognises compressed files by their first bytes, not their names.  gzip, bzip2 and zstd
+  files are decompressed as they are read, but an xz file is unpacked into memory whole, so one
+  that unpacks to more than 256 MiB is reported as an error and skipped.
+
+Watching:
+  With --watch minigrep keeps running after searching everything once, checking the FILEs and
+  directories for changes a few times a second.  Lines added to a file are searched once they
+  are complete, and when a file is changed in any other way only the matches that are new or
+  different are printed.  Stop it with Ctrl-C.
+
+Replacing:
+  With --regex, $1 or ${name} in TEMPLATE is replaced by what that group matched ($$ is a plain
+  $); a plain query's TEMPLATE is used as it is.  --in-place rewrites each file atomically by
+  writing a temporary file next to it and renaming it over the original.
+
+Configuration:
+  Defaults are read from ~/.config/minigrep/config.toml and then from the nearest .minigreprc
+  in the current directory or above.  Both are TOML whose keys are long flag names, like
+  `include = [\"*.rs\"]` or `ignore-case = true`, with presets as [preset.NAME] tables.  They
+  go in front of the command line, so a flag given there overrides a setting with a value and
+  -s undoes ignore-case, but any other setting that turns something on stays on unless
+  --no-config is given.  Flags that write to files or change which lines are selected or how
+  they are reported (like --replace, --count, --invert-match, --multiline, --fuzzy,
+  --max-count, --unique or --json) can't be set in these files.
+  STYLE is a list of SGR codes, eg: --colors match=1;32
+
+Exit status:
+  0 if a line was selected (with -L: a file was listed), 1 if not, and 2 if an error occurred.");
+
+    help
+}
+
+// The values gathered while walking the arguments, before they are turned into a Config
+#[derive(Default)]
+struct Parsed {
+    // Set once -e or -f is seen, even if a pattern file turns out to be empty
+    patterns: Option<Vec<String>>,
+    ignore_case: Option<bool>,
+    matcher: MatcherOptions,
+    mode: Mode,
+    output: OutputOptions,
+    walk: WalkOptions,
+    before: Option<usize>,
+    after: Option<usize>,
+    context: Option<usize>,
+    jobs: Option<usize>,
+    color: ColorChoice,
+    binary: BinaryMode,
+    decompress: bool,
+    no_index: bool,
+    only: Option<Region>,
+    field: Option<Field>,
+    max_count: Option<usize>,
+    sort: SortBy,
+    unique: bool,
+    watch: bool,
+    replace: Option<String>,
+    in_place: bool,
+    dry_run: bool,
+    // A `--preset` to expand before carrying on with the command line
+    preset: Option<String>,
+    include: Vec<String>,
+    exclude: Vec<String>,
+    positional: Vec<String>,
+}
+
+impl Parsed {
+    fn apply(&mut self, flag: Flag, name: &str, value: Option<String>) -> Result<(), ArgsError> {
+        match flag {
+            Flag::Pattern => self.patterns.get_or_insert_with(Vec::new).extend(value),
+            Flag::PatternFile => {
+                let path = value.unwrap_or_default();
+                let contents = read_patterns(&path)
+                    .map_err(|error| ArgsError::PatternFile { path, error })?;
+                let patterns = self.patterns.get_or_insert_with(Vec::new);
+                patterns.extend(contents.lines().map(String::from));
+            }
+            Flag::IgnoreCase => self.ignore_case = Some(true),
+            Flag::CaseSensitive => self.ignore_case = Some(false),
+            Flag::Regex => self.matcher.regex = true,
+            Flag::Word => self.matcher.whole_word = true,
+            Flag::Multiline => self.matcher.multiline = true,
+            Flag::CodeOnly => self.only = Some(Region::Code),
+            Flag::CommentsOnly => self.only = Some(Region::Comment),
+            Flag::Field => {
+                let value = value.unwrap_or_default();
+                let (path, query) = match value.split_once('=') {
+                    Some((path, query)) => (path, Some(query.to_string())),
+                    None => (value.as_str(), None),
+                };
+                if path.split('.').any(str::is_empty) {
+                    return Err(ArgsError::InvalidValue { flag: name.to_string(), value });
+                }
+                self.field = Some(Field::Json(path.split('.').map(String::from).collect()));
+                self.patterns.get_or_insert_with(Vec::new).extend(query);
+            }
+            Flag::CsvColumn => {
+                let column = number(name, value)?;
+                if column == 0 {
+                    let value = column.to_string();
+                    return Err(ArgsError::InvalidValue { flag: name.to_string(), value });
+                }
+                self.field = Some(Field::Csv(column - 1));
+            }
+            Flag::Fuzzy => {
+                let edits = match value {
+                    Some(value) => number(name, Some(value))?,
+                    None => 1,
+                };
+                self.matcher.fuzzy = Some(edits);
+            }
+            Flag::Invert => self.matcher.invert = true,
+            Flag::Count => self.mode = Mode::Count,
+            Flag::FilesWithMatches => self.mode = Mode::FilesWithMatches,
+            Flag::FilesWithoutMatch => self.mode = Mode::FilesWithoutMatch,
+            Flag::MaxCount => self.max_count = Some(number(name, value)?),
+            Flag::Sort => {
+                let value = value.unwrap_or_default();
+                self.sort = SortBy::parse(&value)
+                    .ok_or_else(|| ArgsError::InvalidValue { flag: name.to_string(), value })?;
+            }
+            Flag::Unique => self.unique = true,
+            Flag::LineNumber => self.output.line_number = true,
+            Flag::ByteOffset => self.output.byte_offset = true,
+            Flag::Column => self.output.column = true,
+            Flag::After => self.after = Some(number(name, value)?),
+            Flag::Before => self.before = Some(number(name, value)?),
+            Flag::Context => self.context = Some(number(name, value)?),
+            Flag::Include => self.include.extend(value),
+            Flag::Exclude => self.exclude.extend(value),
+            Flag::Hidden => self.walk.hidden = true,
+            Flag::NoIgnore => self.walk.ignore_files = false,
+            Flag::Jobs => {
+                let jobs = number(name, value)?;
+                if jobs == 0 {
+                    let value = jobs.to_string();
+                    return Err(ArgsError::InvalidValue { flag: name.to_string(), value });
+                }
+                self.jobs = Some(jobs);
+            }
+            Flag::Color => {
+                let value = value.unwrap_or_default();
+                self.color = ColorChoice::parse(&value)
+                    .ok_or_else(|| ArgsError::InvalidValue { flag: name.to_string(), value })?;
+            }
+            Flag::Colors => {
+                let value = value.unwrap_or_default();
+                if !self.output.colors.set(&value) {
+                    return Err(ArgsError::InvalidValue { flag: name.to_string(), value });
+                }
+            }
+            Flag::Json => self.output.json = true,
+            Flag::Text => self.binary = BinaryMode::Text,
+            Flag::Binary => self.binary = BinaryMode::Report,
+            Flag::Decompress => self.decompress = true,
+            Flag::NoIndex => self.no_index = true,
+            Flag::Watch => self.watch = true,
+            Flag::Replace => self.replace = value,
+            Flag::InPlace => self.in_place = true,
+            Flag::DryRun => self.dry_run = true,
+            Flag::Preset => self.preset = value,
+            // Configuration files have been dealt with by the time we get here
+            Flag::NoConfig => {}
+            Flag::Help => return Err(ArgsError::Help),
+            Flag::Version => return Err(ArgsError::Version),
+        }
+        Ok(())
+    }
+}
+
+// The contents of a `-f` file, where `-` is standard input
+fn read_patterns(path: &str) -> io::Result<String> {
+    if path == crate::STDIN {
+        let mut contents = String::new();
+        io::stdin().read_to_string(&mut contents)?;
+        Ok(contents)
+    } else {
+        fs::read_to_string(path)
+    }
+}
+
+fn number(flag: &str, value: Option<String>) -> Result<usize, ArgsError> {
+    let value = value.unwrap_or_default();
+    value.parse().map_err(|_| ArgsError::InvalidValue { flag: flag.to_string(), value })
+}
+
+// Whether the flag `long` takes a value, if it's one a configuration file can set.  Nothing that
+// changes what is searched for, which lines are selected or what is printed for them, writes to
+// files or stops the search can be: a `.minigreprc` in any parent directory (eg: of a cloned
+// repository) is read, and `minigrep token notes.txt` mustn't quietly rewrite notes.txt.  Nor can
+// a flag that the command line couldn't turn off again when it conflicts with one given there
+// (eg: `unique = true` would make every `minigrep -c` an error)
+pub fn takes_value(long: &str) -> Option<bool> {
+    let spec = FLAGS.iter().find(|spec| spec.long == long)?;
+    match spec.flag {
+        Flag::Pattern | Flag::PatternFile | Flag::Preset | Flag::NoConfig | Flag::Help
+        | Flag::Version => None,
+        Flag::Replace | Flag::InPlace | Flag::DryRun | Flag::Watch => None,
+        Flag::Invert | Flag::Count | Flag::FilesWithMatches | Flag::FilesWithoutMatch
+        | Flag::Field | Flag::CsvColumn | Flag::CodeOnly | Flag::CommentsOnly => None,
+        Flag::Multiline | Flag::Fuzzy | Flag::MaxCount | Flag::Unique | Flag::Json => None,
+        _ => Some(!matches!(spec.takes, Takes::Nothing)),
+    }
+}
+
+// Parse the command line (including the program name in position 0)
+// `env_ignore_case` is whether CASE_INSENSITIVE is set and `settings` come from the configuration
+// files; both are passed in rather than read here so tests don't depend on the environment they
+// happen to run in
+pub fn parse(
+    mut args: impl Iterator<Item = String>,
+    env_ignore_case: bool,
+    settings: &Settings,
+) -> Result<Config, ArgsError> {
+    // Skip the 0th index containing calling context
+    args.next();
+
+    let mut parsed = Parsed::default();
+    let mut flags_done = false;
+    // Flags from the configuration files go first so that the command line wins.  They are all
+    // `--flag` or `--flag=value`, so a flag that takes the next argument as its value only ever
+    // comes from `args`
+    let mut pending: VecDeque<String> = settings.defaults.iter().cloned().collect();
+    while let Some(arg) = pending.pop_front().or_else(|| args.next()) {
+        if flags_done || arg == "-" || !arg.starts_with('-') {
+            parsed.positional.push(arg);
+        } else if arg == "--" {
+            flags_done = true;
+        } else if let Some(long) = arg.strip_prefix("--") {
+            let (name, inline) = match long.split_once('=') {
+                Some((name, value)) => (name, Some(value.to_string())),
+                None => (long, None),
+            };
+            let spec = FLAGS.iter()
+                .find(|spec| spec.long == name)
+                .ok_or_else(|| ArgsError::UnknownFlag(arg.clone()))?;
+            let value = match (spec.takes, inline) {
+                (Takes::Value(_) | Takes::OptionalValue(_), Some(value)) => Some(value),
+                (Takes::Value(_), None) => {
+                    Some(args.next().ok_or_else(|| ArgsError::MissingValue(arg.clone()))?)
+                }
+                (Takes::Nothing, Some(_)) => return Err(ArgsError::UnknownFlag(arg.clone())),
+                (Takes::Nothing | Takes::OptionalValue(_), None) => None,
+            };
+            parsed.apply(spec.flag, &arg, value)?;
+            if let Some(name) = parsed.preset.take() {
+                let preset = settings.presets.get(&name).ok_or(ArgsError::UnknownPreset(name))?;
+                for flag in preset.iter().rev() {
+                    pending.push_front(flag.clone());
+                }
+            }
+        } else {
+            // A bundle of short flags; the first one that takes a value swallows the rest of the
+            // bundle, or the next argument if nothing is left
+            let shorts = &arg[1..];
+            for (index, c) in shorts.char_indices() {
+                let name = format!("-{}", c);
+                let spec = FLAGS.iter()
+                    .find(|spec| spec.short == Some(c))
+                    .ok_or_else(|| ArgsError::UnknownFlag(name.clone()))?;
+                if !matches!(spec.takes, Takes::Value(_)) {
+                    parsed.apply(spec.flag, &name, None)?;
+                    continue;
+                }
+
+                let rest = &shorts[index + c.len_utf8()..];
+                let value = if rest.is_empty() {
+                    args.next().ok_or_else(|| ArgsError::MissingValue(name.clone()))?
+                } else {
+                    rest.to_string()
+                };
+                parsed.apply(spec.flag, &name, Some(value))?;
+                break;
+            }
+        }
+    }
+
+    // JSON records describe lines, so the modes that print something else don't fit
+    if parsed.output.json {
+        let other = match parsed.mode {
+            Mode::Lines => None,
+            Mode::Count => Some("--count"),
+            Mode::FilesWithMatches => Some("--files-with-matches"),
+            Mode::FilesWithoutMatch => Some("--files-without-match"),
+        };
+        if let Some(other) = other {
+            return Err(ArgsError::Conflict("--json", other));
+        }
+    }
+
+    if parsed.output.json && parsed.replace.is_some() {
+        return Err(ArgsError::Conflict("--json", "--replace"));
+    }
+    if parsed.matcher.fuzzy.is_some() {
+        if parsed.matcher.regex {
+            return Err(ArgsError::Conflict("--fuzzy", "--regex"));
+        }
+        // Ranking moves lines away from their neighbours
+        if parsed.before.or(parsed.after).or(parsed.context).is_some() {
+            return Err(ArgsError::Conflict("--fuzzy", "--context"));
+        }
+    }
+    if parsed.matcher.multiline {
+        let other = if parsed.matcher.fuzzy.is_some() {
+            Some("--fuzzy")
+        } else if parsed.matcher.invert {
+            Some("--invert-match")
+        } else if parsed.replace.is_some() {
+            Some("--replace")
+        } else {
+            None
+        };
+        if let Some(other) = other {
+            return Err(ArgsError::Conflict("--multiline", other));
+        }
+    }
+    // Telling code from comments means reading every line of a file in order, and picking
+    // matches by where they are means there have to be some.  A replacement would apply to every
+    // match in the line, including the ones that were passed over
+    if let Some(only) = parsed.only {
+        let other = if parsed.matcher.invert {
+            Some("--invert-match")
+        } else if parsed.matcher.multiline {
+            Some("--multiline")
+        } else if parsed.in_place {
+            Some("--in-place")
+        } else if parsed.replace.is_some() {
+            Some("--replace")
+        } else if parsed.watch {
+            Some("--watch")
+        } else {
+            None
+        };
+        if let Some(other) = other {
+            let only = if only == Region::Code { "--code-only" } else { "--comments-only" };
+            return Err(ArgsError::Conflict(only, other));
+        }
+    }
+    // Only the field is matched, so nothing that looks at or changes the rest of the line fits
+    if let Some(field) = &parsed.field {
+        let other = if parsed.matcher.fuzzy.is_some() {
+            Some("--fuzzy")
+        } else if parsed.matcher.multiline {
+            Some("--multiline")
+        } else if parsed.only == Some(Region::Code) {
+            Some("--code-only")
+        } else if parsed.only == Some(Region::Comment) {
+            Some("--comments-only")
+        } else if parsed.replace.is_some() {
+            Some("--replace")
+        } else {
+            None
+        };
+        if let Some(other) = other {
+            let field = if let Field::Json(_) = field { "--field" } else { "--csv-column" };
+            return Err(ArgsError::Conflict(field, other));
+        }
+    }
+    // Unique lines are printed once a file is done, with a count where other modes put more
+    if parsed.unique {
+        let other = match parsed.mode {
+            Mode::Lines => None,
+            Mode::Count => Some("--count"),
+            Mode::FilesWithMatches => Some("--files-with-matches"),
+            Mode::FilesWithoutMatch => Some("--files-without-match"),
+        };
+        let other = other.or(if parsed.before.or(parsed.after).or(parsed.context).is_some() {
+            Some("--context")
+        } else if parsed.matcher.fuzzy.is_some() {
+            Some("--fuzzy")
+        } else if parsed.output.json {
+            Some("--json")
+        } else if parsed.replace.is_some() {
+            Some("--replace")
+        } else if parsed.watch {
+            Some("--watch")
+        } else {
+            None
+        });
+        if let Some(other) = other {
+            return Err(ArgsError::Conflict("--unique", other));
+        }
+    }
+    if parsed.max_count.is_some() {
+        if parsed.in_place {
+            return Err(ArgsError::Conflict("--max-count", "--in-place"));
+        }
+        if parsed.watch {
+            return Err(ArgsError::Conflict("--max-count", "--watch"));
+        }
+    }
+    // Watching prints lines as they turn up, so neither a summary of each file nor anything that
+    // needs the whole of a file at once works
+    if parsed.watch {
+        let other = match parsed.mode {
+            Mode::Lines => None,
+            Mode::Count => Some("--count"),
+            Mode::FilesWithMatches => Some("--files-with-matches"),
+            Mode::FilesWithoutMatch => Some("--files-without-match"),
+        };
+        let other = other.or(if parsed.before.or(parsed.after).or(parsed.context).is_some() {
+            Some("--context")
+        } else if parsed.matcher.multiline {
+            Some("--multiline")
+        } else if parsed.in_place {
+            Some("--in-place")
+        } else if parsed.decompress {
+            Some("--search-zip")
+        } else {
+            None
+        });
+        if let Some(other) = other {
+            return Err(ArgsError::Conflict("--watch", other));
+        }
+    }
+    // Rewriting replaces the matches in the lines that have them, so it only fits selecting the
+    // lines that match and printing nothing but the changes
+    if parsed.in_place {
+        let other = match parsed.mode {
+            Mode::Lines => None,
+            Mode::Count => Some("--count"),
+            Mode::FilesWithMatches => Some("--files-with-matches"),
+            Mode::FilesWithoutMatch => Some("--files-without-match"),
+        };
+        let other = other.or(if parsed.matcher.invert { Some("--invert-match") } else { None });
+        if let Some(other) = other {
+            return Err(ArgsError::Conflict("--in-place", other));
+        }
+    }
+    if parsed.in_place && parsed.replace.is_none() {
+        return Err(ArgsError::Requires("--in-place", "--replace"));
+    }
+    if parsed.dry_run && !parsed.in_place {
+        return Err(ArgsError::Requires("--dry-run", "--in-place"));
+    }
+
+    let mut positional = parsed.positional.into_iter();
+    let patterns = match parsed.patterns {
+        Some(patterns) => patterns,
+        None => vec![positional.next().ok_or(ArgsError::MissingQuery)?],
+    };
+
+    // Everything after the query is a file (or directory) to search.  With no files at all we
+    // read standard input, which is also what a filename of `-` means
+    let mut filenames: Vec<String> = positional.collect();
+    if filenames.is_empty() {
+        filenames.push(crate::STDIN.to_string());
+    }
+    // Standard input can't be read again
+    if parsed.watch && filenames.iter().any(|name| name == crate::STDIN) {
+        return Err(ArgsError::Requires("--watch", "a FILE to watch"));
+    }
+
+    // Flags win over the environment
+    let ignore_case = parsed.ignore_case.unwrap_or(env_ignore_case);
+    let mut matcher_options = parsed.matcher;
+    matcher_options.ignore_case = ignore_case;
+    let matcher =
+        Matcher::with_patterns(&patterns, &matcher_options).map_err(ArgsError::InvalidPattern)?;
+
+    // An explicit -A or -B wins over -C whichever order they were given in
+    let context = ContextOptions {
+        before: parsed.before.or(parsed.context).unwrap_or(0),
+        after: parsed.after.or(parsed.context).unwrap_or(0),
+    };
+
+    let mut walk = parsed.walk;
+    walk.globs = GlobFilter::new(&parsed.include, &parsed.exclude).map_err(ArgsError::InvalidGlob)?;
+
+    Ok(Config {
+        patterns,
+        filenames,
+        case_sensitive: !ignore_case,
+        matcher,
+        mode: parsed.mode,
+        walk,
+        output: parsed.output,
+        context,
+        jobs: parsed.jobs.unwrap_or_else(crate::parallel::default_jobs),
+        color: parsed.color,
+        binary: parsed.binary,
+        decompress: parsed.decompress,
+        replace: parsed.replace,
+        in_place: parsed.in_place,
+        dry_run: parsed.dry_run,
+        use_index: !parsed.no_index,
+        multiline: matcher_options.multiline,
+        only: parsed.only,
+        field: parsed.field,
+        max_count: parsed.max_count,
+        sort: parsed.sort,
+        unique: parsed.unique,
+        watch: parsed.watch,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    fn parse_args(args: &[&str]) -> Result<Config, ArgsError> {
+        let mut all = vec!["minigrep".to_string()];
+        all.extend(args.iter().map(|arg| arg.to_string()));
+        parse(all.into_iter(), false, &Settings::default())
+    }
+
+    #[test]
+    fn many_files_and_globs() {
+        let config = parse_args(&["--include", "*.rs", "query", "a.rs", "b", "--exclude=b"]).unwrap();
+
+        assert_eq!(vec!["query"], config.patterns);
+        assert_eq!(vec!["a.rs", "b"], config.filenames);
+        assert!(config.walk.globs.allows(Path::new("a.rs")));
+        assert!(!config.walk.globs.allows(Path::new("b")));
+    }
+
+    #[test]
+    fn bundled_and_attached_short_flags() {
+        let config = parse_args(&["-inA2", "-B1", "query"]).unwrap();
+
+        assert!(!config.case_sensitive);
+        assert!(config.output.line_number);
+        assert_eq!(ContextOptions { before: 1, after: 2 }, config.context);
+    }
+
+    #[test]
+    fn context_flags() {
+        let config = parse_args(&["-A", "1", "-C", "3", "q", "f"]).unwrap();
+        assert_eq!(ContextOptions { before: 3, after: 1 }, config.context);
+
+        assert!(matches!(
+            parse_args(&["-B", "many", "q", "f"]),
+            Err(ArgsError::InvalidValue { .. })
+        ));
+    }
+
+    #[test]
+    fn double_dash_ends_flags() {
+        let config = parse_args(&["--", "-v", "-"]).unwrap();
+
+        assert_eq!(vec!["-v"], config.patterns);
+        assert_eq!(vec!["-"], config.filenames);
+        assert!(config.matcher.is_match("-v"));
+    }
+
+    #[test]
+    fn several_patterns() {
+        let dir = tempfile::tempdir().unwrap();
+        let list = dir.path().join("patterns.txt");
+        fs::write(&list, "alpha\r\nbeta\n").unwrap();
+        let list = list.to_str().unwrap();
+        let config = parse_args(&["-e", "-x", "-f", list, "--regexp=gamma", "a.txt"]).unwrap();
+
+        assert_eq!(vec!["-x", "alpha", "beta", "gamma"], config.patterns);
+        assert_eq!(vec!["a.txt"], config.filenames);
+        assert!(config.matcher.is_match("the beta"));
+
+        // An empty pattern file means there's nothing to find, not that a QUERY follows
+        fs::write(dir.path().join("empty"), "").unwrap();
+        let config = parse_args(&["-f", dir.path().join("empty").to_str().unwrap(), "a"]).unwrap();
+        assert_eq!(vec!["a"], config.filenames);
+        assert!(!config.matcher.is_match("a"));
+
+        assert!(matches!(
+            parse_args(&["-f", "/no/such/file"]),
+            Err(ArgsError::PatternFile { .. })
+        ));
+    }
+
+    #[test]
+    fn defaults_to_stdin() {
+        assert_eq!(vec!["-"], parse_args(&["query"]).unwrap().filenames);
+    }
+
+    #[test]
+    fn case_sensitivity_precedence() {
+        let case_sensitive = |args: &[&str], env_ignore_case| {
+            let args = args.iter().map(|arg| arg.to_string());
+            parse(args, env_ignore_case, &Settings::default()).unwrap().case_sensitive
+        };
+
+        assert!(case_sensitive(&["mg", "q"], false));
+        assert!(!case_sensitive(&["mg", "q"], true));
+        assert!(case_sensitive(&["mg", "-s", "q"], true));
+        assert!(!case_sensitive(&["mg", "-s", "-i", "q"], false));
+    }
+
+    #[test]
+    fn settings_and_presets() {
+        let settings = Settings::parse(r#"
+            ignore-case = true
+            include = "*.md"
+            colors = { match = "1;32" }
+
+            [preset.rust]
+            include = ["*.rs"]
+            word-regexp = true
+        "#).unwrap();
+        let parse_with = |args: &[&str]| {
+            let mut all = vec!["minigrep".to_string()];
+            all.extend(args.iter().map(|arg| arg.to_string()));
+            parse(all.into_iter(), false, &settings)
+        };
+
+        let config = parse_with(&["q"]).unwrap();
+        assert!(!config.case_sensitive);
+        assert_eq!("1;32", config.output.colors.matched);
+        // The command line wins
+        assert!(parse_with(&["-s", "q"]).unwrap().case_sensitive);
+        // A preset adds to the defaults, in place of itself
+        let config = parse_with(&["--preset", "rust", "rust", "src"]).unwrap();
+        assert!(config.walk.globs.allows(Path::new("src/lib.rs")));
+        assert!(config.walk.globs.allows(Path::new("README.md")));
+        assert!(!config.matcher.is_match("rusty"));
+        assert!(matches!(parse_with(&["--preset=go", "q"]), Err(ArgsError::UnknownPreset(_))));
+        assert!(matches!(parse_with(&["--colors", "text=1", "q"]), Err(ArgsError::InvalidValue { .. })));
+    }
+
+    #[test]
+    fn settings_that_would_conflict() {
+        // Set in a configuration file, these would make flags like `-c` unusable
+        for setting in
+            ["unique = true", "json = true", "multiline = true", "max-count = 1", "fuzzy = 1"]
+        {
+            assert!(Settings::parse(setting).is_err(), "{}", setting);
+        }
+        assert_eq!(
+            Err("'unique' isn't a flag that can be set here".to_string()),
+            Settings::parse("unique = true").map(|_| ())
+        );
+        let settings = Settings::parse("line-number = true").unwrap();
+        let args = ["minigrep", "-c", "abc", "f.txt"].iter().map(|arg| arg.to_string());
+        assert_eq!(Mode::Count, parse(args, false, &settings).unwrap().mode);
+    }
+
+    #[test]
+    fn modes() {
+        assert_eq!(Mode::Count, parse_args(&["-c", "q"]).unwrap().mode);
+        assert_eq!(Mode::FilesWithMatches, parse_args(&["-l", "q"]).unwrap().mode);
+        assert_eq!(Mode::FilesWithoutMatch, parse_args(&["-L", "q"]).unwrap().mode);
+        assert_eq!(Mode::Lines, parse_args(&["q"]).unwrap().mode);
+    }
+
+    #[test]
+    fn jobs() {
+        assert_eq!(3, parse_args(&["-j3", "q"]).unwrap().jobs);
+        assert!(parse_args(&["q"]).unwrap().jobs >= 1);
+        assert!(matches!(parse_args(&["-j", "0", "q"]), Err(ArgsError::InvalidValue { .. })));
+    }
+
+    #[test]
+    fn color() {
+        assert_eq!(ColorChoice::Auto, parse_args(&["q"]).unwrap().color);
+        assert_eq!(ColorChoice::Always, parse_args(&["--color=always", "q"]).unwrap().color);
+        assert_eq!(ColorChoice::Never, parse_args(&["--color", "never", "q"]).unwrap().color);
+        assert!(matches!(parse_args(&["--color=red", "q"]), Err(ArgsError::InvalidValue { .. })));
+    }
+
+    #[test]
+    fn json_conflicts() {
+        assert!(parse_args(&["--json", "q"]).unwrap().output.json);
+        assert!(matches!(parse_args(&["--json", "-c", "q"]), Err(ArgsError::Conflict(..))));
+    }
+
+    #[test]
+    fn binary() {
+        assert_eq!(BinaryMode::Auto, parse_args(&["q"]).unwrap().binary);
+        assert_eq!(BinaryMode::Text, parse_args(&["-a", "q"]).unwrap().binary);
+        assert_eq!(BinaryMode::Report, parse_args(&["-a", "--binary", "q"]).unwrap().binary);
+    }
+
+    #[test]
+    fn replace_flags() {
+        let config = parse_args(&["--replace", "$1", "--in-place", "--dry-run", "q", "f"]).unwrap();
+        assert_eq!(Some("$1".to_string()), config.replace);
+        assert!(config.in_place && config.dry_run);
+
+        let requires = |args: &[&str]| matches!(parse_args(args), Err(ArgsError::Requires(..)));
+        assert!(requires(&["--in-place", "q"]));
+        assert!(requires(&["--replace=x", "--dry-run", "q"]));
+        assert!(matches!(parse_args(&["--replace=x", "--json", "q"]), Err(ArgsError::Conflict(..))));
+        for (flag, other) in [
+            ("-v", "--invert-match"),
+            ("-c", "--count"),
+            ("-l", "--files-with-matches"),
+            ("-L", "--files-without-match"),
+        ] {
+            let result = parse_args(&["--replace=x", "--in-place", flag, "q", "f"]);
+            assert!(matches!(result, Err(ArgsError::Conflict("--in-place", o)) if o == other));
+        }
+    }
+
+    #[test]
+    fn fuzzy() {
+        assert!(!parse_args(&["nobody"]).unwrap().matcher.is_fuzzy());
+        let config = parse_args(&["--fuzzy", "nobody", "f"]).unwrap();
+        assert_eq!(vec!["f"], config.filenames);
+        assert_eq!(Some(1), config.matcher.distance("nobdy"));
+        assert_eq!(None, config.matcher.distance("nbdy"));
+        let config = parse_args(&["--fuzzy=2", "nobody"]).unwrap();
+        assert_eq!(Some(2), config.matcher.distance("nbdy"));
+        assert!(matches!(parse_args(&["--fuzzy=x", "q"]), Err(ArgsError::InvalidValue { .. })));
+        assert!(matches!(parse_args(&["--fuzzy", "--regex", "q"]), Err(ArgsError::Conflict(..))));
+        assert!(matches!(parse_args(&["--fuzzy", "-A1", "q"]), Err(ArgsError::Conflict(..))));
+    }
+
+    #[test]
+    fn watch() {
+        assert!(parse_args(&["--watch", "q", "log"]).unwrap().watch);
+        assert!(matches!(parse_args(&["--watch", "q"]), Err(ArgsError::Requires(..))));
+        for other in ["-c", "-l", "-C2", "-U", "-z"] {
+            let result = parse_args(&["--watch", other, "q", "log"]);
+            assert!(matches!(result, Err(ArgsError::Conflict("--watch", _))), "{}", other);
+        }
+    }
+
+    #[test]
+    fn code_or_comments() {
+        assert_eq!(Some(Region::Code), parse_args(&["--code-only", "q"]).unwrap().only);
+        assert_eq!(None, parse_args(&["q"]).unwrap().only);
+        // The last one wins
+        let config = parse_args(&["--code-only", "--comments-only", "q"]).unwrap();
+        assert_eq!(Some(Region::Comment), config.only);
+        for other in ["-v", "-U", "--watch", "--replace=x"] {
+            let result = parse_args(&["--comments-only", other, "q", "f"]);
+            assert!(matches!(result, Err(ArgsError::Conflict("--comments-only", _))), "{}", other);
+        }
+    }
+
+    #[test]
+    fn fields() {
+        let config = parse_args(&["--field", "req.method=GET", "log"]).unwrap();
+        assert_eq!(Some(Field::Json(vec!["req".to_string(), "method".to_string()])), config.field);
+        assert_eq!(vec!["GET"], config.patterns);
+        assert_eq!(vec!["log"], config.filenames);
+        let config = parse_args(&["--field=level", "-e", "warn", "--field=level=error"]).unwrap();
+        assert_eq!(vec!["warn", "error"], config.patterns);
+        let config = parse_args(&["--csv-column", "3", "q", "log.csv"]).unwrap();
+        assert_eq!(Some(Field::Csv(2)), config.field);
+
+        for bad in [&["--field", "a..b", "q"][..], &["--field", "=q"], &["--csv-column=0", "q"]] {
+            assert!(matches!(parse_args(bad), Err(ArgsError::InvalidValue { .. })), "{:?}", bad);
+        }
+        let result = parse_args(&["--csv-column", "1", "--fuzzy", "q"]);
+        assert!(matches!(result, Err(ArgsError::Conflict("--csv-column", "--fuzzy"))));
+    }
+
+    #[test]
+    fn limits_and_summaries() {
+        let config = parse_args(&["-m2", "--sort", "mtime", "--unique", "q"]).unwrap();
+        let limits = |config: Config| (config.max_count, config.sort, config.unique);
+        assert_eq!((Some(2), SortBy::Modified, true), limits(config));
+        assert_eq!((None, SortBy::None, false), limits(parse_args(&["q"]).unwrap()));
+
+        assert!(matches!(parse_args(&["--sort=size", "q"]), Err(ArgsError::InvalidValue { .. })));
+        for other in ["-c", "-C1", "--json", "--fuzzy", "--replace=x"] {
+            let result = parse_args(&["--unique", other, "q"]);
+            assert!(matches!(result, Err(ArgsError::Conflict("--unique", _))), "{}", other);
+        }
+        let result = parse_args(&["-m1", "--watch", "q", "log"]);
+        assert!(matches!(result, Err(ArgsError::Conflict("--max-count", "--watch"))));
+    }
+
+    #[test]
+    fn multiline() {
+        assert!(parse_args(&["-U", "--regex", r"a\nb"]).unwrap().multiline);
+        assert!(!parse_args(&["q"]).unwrap().multiline);
+        for other in ["-v", "--fuzzy", "--replace=x"] {
+            let result = parse_args(&["-U", other, "q"]);
+            assert!(matches!(result, Err(ArgsError::Conflict("--multiline", _))), "{}", other);
+        }
+    }
+
+    #[test]
+    fn usage_errors() {
+        assert!(matches!(parse_args(&[]), Err(ArgsError::MissingQuery)));
+        assert!(matches!(parse_args(&["--bogus", "q"]), Err(ArgsError::UnknownFlag(_))));
+        assert!(matches!(parse_args(&["-x", "q"]), Err(ArgsError::UnknownFlag(_))));
+        assert!(matches!(parse_args(&["q", "--include"]), Err(ArgsError::MissingValue(_))));
+        assert!(matches!(parse_args(&["--regex", "("]), Err(ArgsError::InvalidPattern(_))));
+        assert!(matches!(parse_args(&["--include", "[", "q"]), Err(ArgsError::InvalidGlob(_))));
+        assert!(matches!(parse_args(&["q", "--help"]), Err(ArgsError::Help)));
+        assert!(matches!(parse_args(&["-V"]), Err(ArgsError::Version)));
+    }
+
+    #[test]
+    fn help_lists_every_flag() {
+        let help = help();
+        for spec in FLAGS {
+            assert!(help.contains(&format!("--{}", spec.long)));
+        }
+        assert!(help.contains("CASE_INSENSITIVE"));
+    }
+}
@@ -0,0 +1,170 @@
+// Context lines (-A/-B/-C) for minigrep
+// The tracker is fed every line of a file in order and decides which ones are worth printing:
+// matches always, plus up to `before` lines leading up to a match and `after` lines following one.
+// Windows that overlap or touch are merged into a single group and a `--` separator is emitted
+// between groups that aren't adjacent, just like grep
+use std::collections::VecDeque;
+
+// How many lines of context to show around each match
+#[derive(Clone, Copy, Default, Debug, PartialEq)]
+pub struct ContextOptions {
+    pub before: usize,
+    pub after: usize,
+}
+
+impl ContextOptions {
+    pub fn is_enabled(&self) -> bool {
+        self.before > 0 || self.after > 0
+    }
+}
+
+// A line printed because it is near a match rather than because it matched
+#[derive(Debug, PartialEq)]
+pub struct ContextLine<'a> {
+    pub line_number: usize,
+    pub byte_offset: usize,
+    pub line: &'a str,
+}
+
+// What to print next.  `M` is the type used for matching lines and `C` for context lines; they
+// differ because a context line may have to be held on to until we know a match follows it
+#[derive(Debug, PartialEq)]
+pub enum Event<M, C> {
+    Match(M),
+    Context(C),
+    Separator,
+}
+
+pub struct ContextTracker<C> {
+    options: ContextOptions,
+    // The most recent non-matching lines, kept in case a match turns up
+    buffer: VecDeque<(usize, C)>,
+    // How many more lines still belong to the after-context of the last match
+    after_left: usize,
+    // The line number of the last line emitted
+    last_emitted: Option<usize>,
+}
+
+impl<C> ContextTracker<C> {
+    pub fn new(options: ContextOptions) -> ContextTracker<C> {
+        ContextTracker {
+            options,
+            buffer: VecDeque::with_capacity(options.before),
+            after_left: 0,
+            last_emitted: None,
+        }
+    }
+
+    // Feed a matching line
+    pub fn push_match<M>(&mut self, line_number: usize, m: M, events: &mut Vec<Event<M, C>>) {
+        let first = self.buffer.front().map_or(line_number, |(n, _)| *n);
+        self.start_group(first, events);
+
+        for (number, line) in self.buffer.drain(..) {
+            events.push(Event::Context(line));
+            self.last_emitted = Some(number);
+        }
+        events.push(Event::Match(m));
+        self.last_emitted = Some(line_number);
+        self.after_left = self.options.after;
+    }
+
+    // Feed a line that didn't match.  The line is only built (via `line`) when it might actually
+    // be printed, which saves allocating copies of lines when streaming
+    pub fn push_other<M>(
+        &mut self,
+        line_number: usize,
+        line: impl FnOnce() -> C,
+        events: &mut Vec<Event<M, C>>,
+    ) {
+        if self.after_left > 0 {
+            self.after_left -= 1;
+            events.push(Event::Context(line()));
+            self.last_emitted = Some(line_number);
+        } else if self.options.before > 0 {
+            if self.buffer.len() == self.options.before {
+                self.buffer.pop_front();
+            }
+            self.buffer.push_back((line_number, line()));
+        }
+    }
+
+    // Emit a separator if the line about to be printed doesn't directly follow the previous one
+    fn start_group<M>(&mut self, first_line: usize, events: &mut Vec<Event<M, C>>) {
+        if !self.options.is_enabled() {
+            return;
+        }
+
+        if let Some(last) = self.last_emitted {
+            if last + 1 < first_line {
+                events.push(Event::Separator);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Run the tracker over `matches` (one bool per line) and render the result as a string of
+    // line numbers with `m` for matches, `c` for context and `|` for separators
+    fn render(options: ContextOptions, matches: &[bool]) -> String {
+        let mut tracker = ContextTracker::new(options);
+        let mut events = Vec::new();
+        for (index, &is_match) in matches.iter().enumerate() {
+            let number = index + 1;
+            if is_match {
+                tracker.push_match(number, number, &mut events);
+            } else {
+                tracker.push_other(number, || number, &mut events);
+            }
+        }
+
+        events.iter()
+            .map(|event| match event {
+                Event::Match(n) => format!("{}m", n),
+                Event::Context(n) => format!("{}c", n),
+                Event::Separator => "|".to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    const LINES: [bool; 12] = [
+        false, false, true, false, false, false, false, false, true, false, true, false,
+    ];
+
+    #[test]
+    fn no_context() {
+        assert_eq!("3m 9m 11m", render(ContextOptions::default(), &LINES));
+    }
+
+    #[test]
+    fn after_context() {
+        let options = ContextOptions { before: 0, after: 1 };
+
+        assert_eq!("3m 4c | 9m 10c 11m 12c", render(options, &LINES));
+    }
+
+    #[test]
+    fn before_context() {
+        let options = ContextOptions { before: 2, after: 0 };
+
+        assert_eq!("1c 2c 3m | 7c 8c 9m 10c 11m", render(options, &LINES));
+    }
+
+    #[test]
+    fn overlapping_windows_merge() {
+        let options = ContextOptions { before: 2, after: 2 };
+
+        assert_eq!("1c 2c 3m 4c 5c | 7c 8c 9m 10c 11m 12c", render(options, &LINES));
+    }
+
+    #[test]
+    fn touching_windows_have_no_separator() {
+        let options = ContextOptions { before: 3, after: 2 };
+
+        assert_eq!("1c 2c 3m 4c 5c 6c 7c 8c 9m 10c 11m 12c", render(options, &LINES));
+    }
+}
@@ -0,0 +1,154 @@
+// `-z`: searching inside compressed files
+// Files are recognised by their first few bytes rather than their names, so a log rotated to
+// `app.log.1` is found to be gzip all the same.  Every decoder is pure Rust, so this doesn't
+// depend on which compression libraries happen to be installed
+use std::io::{self, BufRead, BufReader, Cursor, Write};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Gzip,
+    Bzip2,
+    Xz,
+    Zstd,
+}
+
+const MAGIC: &[(&[u8], Format)] = &[
+    (&[0x1f, 0x8b], Format::Gzip),
+    (b"BZh", Format::Bzip2),
+    (&[0xfd, b'7', b'z', b'X', b'Z', 0x00], Format::Xz),
+    (&[0x28, 0xb5, 0x2f, 0xfd], Format::Zstd),
+];
+
+impl Format {
+    // The format of a file starting with `start`, if it is compressed
+    pub fn detect(start: &[u8]) -> Option<Format> {
+        MAGIC.iter()
+            .find(|(magic, _)| start.starts_with(magic))
+            .map(|&(_, format)| format)
+    }
+}
+
+// lzma-rs can only decompress xz into a writer, so an xz file is held in memory whole.  Refusing
+// the ones that unpack to more than this keeps a huge archive from using it all up
+pub const XZ_LIMIT: usize = 256 << 20;
+
+// A reader of the decompressed contents of `reader`
+pub fn decoder<'a>(
+    format: Format,
+    reader: Box<dyn BufRead + 'a>,
+) -> io::Result<Box<dyn BufRead + 'a>> {
+    Ok(match format {
+        // The Multi decoders carry on past the end of the first member, like `zcat` does
+        Format::Gzip => Box::new(BufReader::new(flate2::bufread::MultiGzDecoder::new(reader))),
+        Format::Bzip2 => Box::new(BufReader::new(bzip2::bufread::MultiBzDecoder::new(reader))),
+        Format::Xz => Box::new(Cursor::new(xz_decompress(reader, XZ_LIMIT)?)),
+        Format::Zstd => {
+            let decoder = ruzstd::decoding::StreamingDecoder::new(reader).map_err(invalid)?;
+            Box::new(BufReader::new(decoder))
+        }
+    })
+}
+
+fn xz_decompress<'a>(mut reader: Box<dyn BufRead + 'a>, limit: usize) -> io::Result<Vec<u8>> {
+    let mut contents = Limited { bytes: Vec::new(), limit };
+    match lzma_rs::xz_decompress(&mut reader, &mut contents) {
+        Ok(()) => Ok(contents.bytes),
+        // Either reading the file or Limited failed
+        Err(lzma_rs::error::Error::IoError(e)) => Err(e),
+        Err(e) => Err(invalid(e)),
+    }
+}
+
+// A Vec that refuses to grow past `limit` bytes
+struct Limited {
+    bytes: Vec<u8>,
+    limit: usize,
+}
+
+impl Write for Limited {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.bytes.len() + buf.len() > self.limit {
+            let message = format!("xz file unpacks to more than {} MiB", self.limit >> 20);
+            return Err(io::Error::other(message));
+        }
+        self.bytes.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn invalid<E: std::error::Error + Send + Sync + 'static>(e: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    const TEXT: &str = "first line\nsecond line\n";
+
+    // Compress TEXT in every format we understand, the same way the real tools would
+    fn compressed(format: Format) -> Vec<u8> {
+        match format {
+            Format::Gzip => {
+                let mut encoder = flate2::write::GzEncoder::new(Vec::new(), Default::default());
+                encoder.write_all(TEXT.as_bytes()).unwrap();
+                encoder.finish().unwrap()
+            }
+            Format::Bzip2 => {
+                let mut encoder = bzip2::write::BzEncoder::new(Vec::new(), Default::default());
+                encoder.write_all(TEXT.as_bytes()).unwrap();
+                encoder.finish().unwrap()
+            }
+            Format::Xz => {
+                let mut out = Vec::new();
+                lzma_rs::xz_compress(&mut TEXT.as_bytes(), &mut out).unwrap();
+                out
+            }
+            Format::Zstd => {
+                let level = ruzstd::encoding::CompressionLevel::Fastest;
+                ruzstd::encoding::compress_to_vec(TEXT.as_bytes(), level)
+            }
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        for format in [Format::Gzip, Format::Bzip2, Format::Xz, Format::Zstd] {
+            let data = compressed(format);
+            assert_eq!(Some(format), Format::detect(&data));
+
+            let mut decompressed = String::new();
+            let mut reader = decoder(format, Box::new(&data[..])).unwrap();
+            reader.read_to_string(&mut decompressed).unwrap();
+            assert_eq!(TEXT, decompressed, "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn xz_limit() {
+        let data = compressed(Format::Xz);
+
+        assert_eq!(TEXT.as_bytes(), &xz_decompress(Box::new(&data[..]), TEXT.len()).unwrap()[..]);
+        assert!(xz_decompress(Box::new(&data[..]), TEXT.len() - 1).is_err());
+    }
+
+    #[test]
+    fn plain_text_is_not_compressed() {
+        assert_eq!(None, Format::detect(TEXT.as_bytes()));
+        assert_eq!(None, Format::detect(b""));
+    }
+
+    #[test]
+    fn corrupt_data() {
+        let mut data = compressed(Format::Gzip);
+        data.truncate(data.len() / 2);
+        let mut reader = decoder(Format::Gzip, Box::new(&data[..])).unwrap();
+
+        assert!(reader.read_to_string(&mut String::new()).is_err());
+    }
+}
@@ -0,0 +1,191 @@
+// `--field` and `--csv-column`: searching one field of each record of a structured log
+// Every line is taken to be a record (a JSON object for JSON Lines, a row for CSV), and the query
+// is matched against the value of one field rather than the whole line.  The record itself is
+// still what gets printed, so everything downstream of choosing lines works as usual.  A value
+// that appears in the line just as it is (no escapes) has its matches highlighted in place
+// A line that isn't a valid record is reported and skipped, since one garbled line shouldn't end
+// the search of a log.  A record without the field is simply not selected
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+
+use serde_json::value::RawValue;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Field {
+    // `--field`: the value at this path of keys in a JSON object, eg: `request.method`
+    Json(Vec<String>),
+    // `--csv-column`: the column at this index, counting from 0
+    Csv(usize),
+}
+
+// The value of a field in a record
+#[derive(Debug, PartialEq)]
+pub struct Value<'a> {
+    pub text: Cow<'a, str>,
+    // Where the field starts in the line, past any opening quote
+    pub at: usize,
+    // Whether `text` is what the line has at `at`, so positions in one are positions in the other
+    pub verbatim: bool,
+}
+
+impl Field {
+    // The field in `line`, or None if the record doesn't have it.  An invalid record is an error
+    // saying what's wrong with it
+    pub fn extract<'a>(&self, line: &'a str) -> Result<Option<Value<'a>>, String> {
+        match self {
+            Field::Json(path) => json_field(line, path),
+            Field::Csv(column) => csv_field(line, *column),
+        }
+    }
+}
+
+fn json_field<'a>(line: &'a str, path: &[String]) -> Result<Option<Value<'a>>, String> {
+    // Blank lines between records are harmless
+    if line.trim().is_empty() {
+        return Ok(None);
+    }
+    let object: BTreeMap<String, &RawValue> =
+        serde_json::from_str(line).map_err(|e| format!("invalid JSON record: {}", e))?;
+    let mut raw = match path.first().and_then(|key| object.get(key)) {
+        Some(raw) => *raw,
+        None => return Ok(None),
+    };
+    for key in &path[1..] {
+        // Anything but an object has no fields to look into
+        let object: BTreeMap<String, &RawValue> = match serde_json::from_str(raw.get()) {
+            Ok(object) => object,
+            Err(_) => return Ok(None),
+        };
+        raw = match object.get(key) {
+            Some(raw) => *raw,
+            None => return Ok(None),
+        };
+    }
+
+    // The raw value borrows from `line`, so its position follows from where it starts
+    let raw = raw.get();
+    let at = raw.as_ptr() as usize - line.as_ptr() as usize;
+    if !raw.starts_with('"') {
+        // Numbers, booleans, null, arrays and objects are matched as they are written
+        return Ok(Some(Value { text: Cow::Borrowed(raw), at, verbatim: true }));
+    }
+    let text: String =
+        serde_json::from_str(raw).map_err(|e| format!("invalid JSON record: {}", e))?;
+    let inner = &raw[1..raw.len() - 1];
+    if text == inner {
+        Ok(Some(Value { text: Cow::Borrowed(inner), at: at + 1, verbatim: true }))
+    } else {
+        Ok(Some(Value { text: Cow::Owned(text), at: at + 1, verbatim: false }))
+    }
+}
+
+// Fields are separated by commas and may be quoted with `"`, with `""` for a quote inside one.
+// Records are one per line, so a quoted field can't contain a line ending
+fn csv_field(line: &str, column: usize) -> Result<Option<Value<'_>>, String> {
+    let bytes = line.as_bytes();
+    let mut start = 0;
+    let mut index = 0;
+    loop {
+        let invalid = |problem| format!("invalid CSV record: {} in column {}", problem, index + 1);
+        let (value, end) = if bytes.get(start) == Some(&b'"') {
+            let (value, close) =
+                quoted(line, start + 1).ok_or_else(|| invalid("unterminated quote"))?;
+            if !matches!(bytes.get(close + 1), None | Some(b',')) {
+                return Err(invalid("text after a quote"));
+            }
+            (value, close + 1)
+        } else {
+            let end = line[start..].find(',').map_or(line.len(), |i| start + i);
+            let text = Cow::Borrowed(&line[start..end]);
+            (Value { text, at: start, verbatim: true }, end)
+        };
+
+        if index == column {
+            return Ok(Some(value));
+        }
+        if end == line.len() {
+            return Ok(None);
+        }
+        start = end + 1;
+        index += 1;
+    }
+}
+
+// The quoted field whose text starts at `start`, and where its closing quote is
+fn quoted(line: &str, start: usize) -> Option<(Value<'_>, usize)> {
+    let mut text = String::new();
+    let mut from = start;
+    loop {
+        let close = from + line[from..].find('"')?;
+        text.push_str(&line[from..close]);
+        if line[close + 1..].starts_with('"') {
+            text.push('"');
+            from = close + 2;
+            continue;
+        }
+
+        let value = if close - start == text.len() {
+            Value { text: Cow::Borrowed(&line[start..close]), at: start, verbatim: true }
+        } else {
+            Value { text: Cow::Owned(text), at: start, verbatim: false }
+        };
+        return Some((value, close));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(field: &Field, line: &str) -> Option<String> {
+        field.extract(line).unwrap().map(|value| value.text.into_owned())
+    }
+
+    #[test]
+    fn json_fields() {
+        let line = r#"{"level": "error", "code": 503, "req": {"path": "/a\"b", "ok": false}}"#;
+        let field = |path: &str| Field::Json(path.split('.').map(String::from).collect());
+
+        let level = field("level").extract(line).unwrap().unwrap();
+        assert_eq!(Value { text: Cow::Borrowed("error"), at: 11, verbatim: true }, level);
+        assert_eq!(Some("503".to_string()), text(&field("code"), line));
+        assert_eq!(Some("false".to_string()), text(&field("req.ok"), line));
+        let path = field("req.path").extract(line).unwrap().unwrap();
+        assert_eq!(("/a\"b", false), (path.text.as_ref(), path.verbatim));
+        assert_eq!(None, text(&field("missing"), line));
+        assert_eq!(None, text(&field("level.nested"), line));
+        assert_eq!(None, text(&field("level"), "  "));
+    }
+
+    #[test]
+    fn invalid_json_records() {
+        let field = Field::Json(vec!["level".to_string()]);
+
+        assert!(field.extract(r#"{"level": "error""#).is_err());
+        assert!(field.extract("[1, 2]").is_err());
+        assert!(field.extract("level=error").is_err());
+    }
+
+    #[test]
+    fn csv_columns() {
+        let line = r#"2024-01-01,"error, bad",42,"say ""hi""",,"#;
+
+        assert_eq!(Some("2024-01-01".to_string()), text(&Field::Csv(0), line));
+        let quoted = Field::Csv(1).extract(line).unwrap().unwrap();
+        assert_eq!(Value { text: Cow::Borrowed("error, bad"), at: 12, verbatim: true }, quoted);
+        assert_eq!(Some("42".to_string()), text(&Field::Csv(2), line));
+        let escaped = Field::Csv(3).extract(line).unwrap().unwrap();
+        assert_eq!(("say \"hi\"", false), (escaped.text.as_ref(), escaped.verbatim));
+        assert_eq!(Some(String::new()), text(&Field::Csv(4), line));
+        assert_eq!(Some(String::new()), text(&Field::Csv(5), line));
+        assert_eq!(None, text(&Field::Csv(6), line));
+    }
+
+    #[test]
+    fn invalid_csv_records() {
+        assert!(Field::Csv(1).extract(r#"a,"unterminated"#).is_err());
+        assert!(Field::Csv(1).extract(r#""quoted"text,b"#).is_err());
+        // Columns after the first problem aren't looked at
+        assert!(Field::Csv(0).extract(r#"a,"unterminated"#).is_ok());
+    }
+}
@@ -0,0 +1,153 @@
+// `--fuzzy`: approximate matching for finding misspelled words
+// A line matches when some part of it can be turned into the query with at most `max_edits`
+// single character insertions, deletions or substitutions (the Levenshtein distance).  This is
+// Sellers' algorithm: the usual edit distance table between the query and the line, except that
+// a match may start anywhere in the line for free.  Only one column of the table is kept, and
+// every cell also remembers where in the line its alignment started so we can report a range
+use std::ops::Range;
+
+pub struct Fuzzy {
+    queries: Vec<Vec<char>>,
+    max_edits: usize,
+    ignore_case: bool,
+}
+
+// One cell of the table: how many edits the best alignment so far needs, and where it started
+#[derive(Clone, Copy)]
+struct Cell {
+    edits: usize,
+    start: usize,
+}
+
+impl Cell {
+    // Fewer edits wins, and then the shorter match
+    fn best(self, other: Cell) -> Cell {
+        if other.edits < self.edits || other.edits == self.edits && other.start > self.start {
+            other
+        } else {
+            self
+        }
+    }
+}
+
+impl Fuzzy {
+    pub fn new(queries: &[&str], max_edits: usize, ignore_case: bool) -> Fuzzy {
+        let queries = queries.iter()
+            .map(|query| query.chars().map(|c| fold_char(c, ignore_case)).collect())
+            .collect();
+
+        Fuzzy { queries, max_edits, ignore_case }
+    }
+
+    // The leftmost approximate match of any query starting at or after byte `from`, along with
+    // the number of edits it needs
+    pub fn find_at(&self, line: &str, from: usize) -> Option<(Range<usize>, usize)> {
+        self.queries.iter()
+            .filter_map(|query| self.find_query(query, line, from))
+            .min_by_key(|(range, edits)| (range.start, *edits))
+    }
+
+    // Scan the line until the first place a match of `query` ends, then keep going for as long
+    // as the next character doesn't make that match worse: in `noboddy` the query `nobody`
+    // should match all of `noboddy` rather than stop at `nobod`
+    fn find_query(&self, query: &[char], line: &str, from: usize) -> Option<(Range<usize>, usize)> {
+        // A query this short is within reach of the empty string, so everything matches
+        if query.len() <= self.max_edits {
+            return Some((from..from, query.len()));
+        }
+
+        // column[i] is the best alignment of the first `i` characters of the query ending at the
+        // current position in the line
+        let mut column: Vec<Cell> =
+            (0..=query.len()).map(|i| Cell { edits: i, start: from }).collect();
+        let mut next = Vec::with_capacity(column.len());
+        let mut found: Option<(Range<usize>, usize)> = None;
+        for (offset, c) in line[from..].char_indices() {
+            let end = from + offset + c.len_utf8();
+            let c = fold_char(c, self.ignore_case);
+
+            next.clear();
+            next.push(Cell { edits: 0, start: end });
+            for (i, &q) in query.iter().enumerate() {
+                let substitute = Cell {
+                    edits: column[i].edits + usize::from(q != c),
+                    start: column[i].start,
+                };
+                let extra_in_line =
+                    Cell { edits: column[i + 1].edits + 1, start: column[i + 1].start };
+                let missing_from_line = Cell { edits: next[i].edits + 1, start: next[i].start };
+                next.push(substitute.best(extra_in_line).best(missing_from_line));
+            }
+            std::mem::swap(&mut column, &mut next);
+
+            let Cell { edits, start } = column[query.len()];
+            match found {
+                Some((_, best)) if edits <= best => found = Some((start..end, edits)),
+                Some(_) => break,
+                None if edits <= self.max_edits => found = Some((start..end, edits)),
+                None => {}
+            }
+        }
+
+        found
+    }
+}
+
+// Characters are compared one at a time, so ignoring case uses simple lowercasing rather than
+// the full folding plain queries get
+fn fold_char(c: char, ignore_case: bool) -> char {
+    if ignore_case {
+        c.to_lowercase().next().unwrap_or(c)
+    } else {
+        c
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn find(query: &str, max_edits: usize, line: &str) -> Option<(Range<usize>, usize)> {
+        Fuzzy::new(&[query], max_edits, false).find_at(line, 0)
+    }
+
+    #[test]
+    fn exact_match_needs_no_edits() {
+        assert_eq!(Some((4..10, 0)), find("nobody", 1, "I'm nobody!"));
+    }
+
+    #[test]
+    fn one_edit_of_each_kind() {
+        // Missing, extra and wrong characters
+        assert_eq!(Some((4..9, 1)), find("nobody", 1, "I'm nobdy!"));
+        assert_eq!(Some((4..11, 1)), find("nobody", 1, "I'm noboddy!"));
+        assert_eq!(Some((4..10, 1)), find("nobody", 1, "I'm nabody!"));
+    }
+
+    #[test]
+    fn too_many_edits() {
+        assert_eq!(None, find("nobody", 1, "I'm nbdy!"));
+        assert_eq!(Some((4..8, 2)), find("nobody", 2, "I'm nbdy!"));
+    }
+
+    #[test]
+    fn keeps_going_while_the_match_holds_up() {
+        // `hell` is already one edit away from `hello`, but `hello` itself needs none
+        assert_eq!(Some((0..5, 0)), find("hello", 1, "hello, world"));
+        assert_eq!(Some((0..4, 1)), find("abc", 1, "abxc"));
+    }
+
+    #[test]
+    fn offsets_are_bytes() {
+        assert_eq!(Some((3..7, 1)), find("café", 1, "Le cafe"));
+        assert_eq!(Some((3..8, 0)), Fuzzy::new(&["CAFÉ"], 1, true).find_at("à café", 0));
+    }
+
+    #[test]
+    fn leftmost_query_wins() {
+        let fuzzy = Fuzzy::new(&["world", "hello"], 1, false);
+
+        assert_eq!(Some((0..4, 1)), fuzzy.find_at("helo wrld", 0));
+        assert_eq!(Some((5..9, 1)), fuzzy.find_at("helo wrld", 2));
+    }
+}
@@ -0,0 +1,341 @@
+// A trigram index for searching the same big tree over and over
+// `minigrep index build DIR` records, for every file under DIR, the set of three byte sequences
+// (trigrams) it contains.  A plain query can only occur in a file that contains every trigram of
+// the query, so a search of DIR reads just the files that pass that test and skips the rest
+// without opening them.  Files are recorded with their modification time and size: rebuilding
+// only reads the files that changed, and a search treats a file the index doesn't know about (or
+// knows an older version of) as a candidate, so a stale index makes searches slower but never
+// wrong
+// Trigrams are taken from the text with its case folded (see `unicode`).  Folding works one
+// character at a time, so a file containing a query also contains the folded query, and the same
+// index serves case sensitive and case insensitive searches alike
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use crate::replace::write_atomically;
+use crate::unicode;
+use crate::walk::{self, WalkOptions};
+
+// The index lives at the top of the tree it describes.  Being hidden keeps it out of searches
+pub const FILE_NAME: &str = ".minigrep-index";
+const MAGIC: &[u8] = b"minigrep index 1\n";
+
+pub struct Index {
+    // Keyed by the path relative to the indexed directory
+    files: BTreeMap<String, Entry>,
+}
+
+struct Entry {
+    stamp: Stamp,
+    // Sorted and without duplicates
+    trigrams: Vec<u32>,
+}
+
+// What we check to tell whether a file changed since it was indexed
+#[derive(Clone, Copy, PartialEq)]
+struct Stamp {
+    modified_secs: u64,
+    modified_nanos: u32,
+    len: u64,
+}
+
+impl Stamp {
+    fn of(path: &Path) -> io::Result<Stamp> {
+        let metadata = fs::metadata(path)?;
+        let modified = metadata.modified()?.duration_since(UNIX_EPOCH).unwrap_or_default();
+        Ok(Stamp {
+            modified_secs: modified.as_secs(),
+            modified_nanos: modified.subsec_nanos(),
+            len: metadata.len(),
+        })
+    }
+}
+
+// What `build` did
+pub struct IndexStats {
+    pub files: usize,
+    // Files that were new or had changed and so were read again
+    pub updated: usize,
+    // Files that were indexed before but are gone now, or couldn't be read this time
+    pub removed: usize,
+}
+
+// Create or bring up to date the index of `root`
+pub fn build(root: &Path) -> io::Result<IndexStats> {
+    let (paths, errors) = walk::files(root, &WalkOptions::default());
+    // What we couldn't walk is left out of the index just like a file we couldn't read
+    for error in &errors {
+        eprintln!("minigrep: {}", error);
+    }
+    index_files(root, paths)
+}
+
+// `build` with the files found under `root`
+fn index_files(root: &Path, paths: Vec<PathBuf>) -> io::Result<IndexStats> {
+    let mut old = open(root).map_or_else(BTreeMap::new, |index| index.files);
+    let mut files = BTreeMap::new();
+    let mut updated = 0;
+    let mut removed = 0;
+    for path in paths {
+        let key = match key(root, &path) {
+            Some(key) => key,
+            None => continue,
+        };
+        let previous = old.remove(&key);
+        let indexed_before = previous.is_some();
+        let entry = Stamp::of(&path).and_then(|stamp| match previous {
+            Some(entry) if entry.stamp == stamp => Ok(entry),
+            _ => {
+                let contents = fs::read(&path)?;
+                updated += 1;
+                Ok(Entry { stamp, trigrams: trigrams(&String::from_utf8_lossy(&contents)) })
+            }
+        });
+        match entry {
+            Ok(entry) => {
+                files.insert(key, entry);
+            }
+            // A file we can't read (eg: one deleted since the walk found it) is left out of the
+            // index, so searches never skip it, rather than failing the whole build
+            Err(e) => {
+                eprintln!("minigrep: {}: {}", path.display(), e);
+                removed += usize::from(indexed_before);
+            }
+        }
+    }
+
+    let index = Index { files };
+    write_atomically(&root.join(FILE_NAME), &index.encode())?;
+    Ok(IndexStats { files: index.files.len(), updated, removed: removed + old.len() })
+}
+
+// The index of `root`, if it has one that we can read
+pub fn open(root: &Path) -> Option<Index> {
+    let data = fs::read(root.join(FILE_NAME)).ok()?;
+    Index::decode(&data)
+}
+
+impl Index {
+    // Whether the file at `path` (under `root`) might contain any of `queries`, given as the
+    // trigrams of each query (see `query_trigrams`)
+    pub fn may_contain(&self, root: &Path, path: &Path, queries: &[Vec<u32>]) -> bool {
+        let entry = match key(root, path).and_then(|key| self.files.get(&key)) {
+            Some(entry) => entry,
+            None => return true,
+        };
+        if Stamp::of(path).ok() != Some(entry.stamp) {
+            return true;
+        }
+
+        queries.iter().any(|query| {
+            query.iter().all(|trigram| entry.trigrams.binary_search(trigram).is_ok())
+        })
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        write_number(&mut out, self.files.len() as u64);
+        for (path, entry) in &self.files {
+            write_number(&mut out, path.len() as u64);
+            out.extend_from_slice(path.as_bytes());
+            write_number(&mut out, entry.stamp.modified_secs);
+            write_number(&mut out, u64::from(entry.stamp.modified_nanos));
+            write_number(&mut out, entry.stamp.len);
+            write_number(&mut out, entry.trigrams.len() as u64);
+            // Sorted trigrams are stored as the gaps between them, which are mostly small
+            let mut previous = 0;
+            for &trigram in &entry.trigrams {
+                write_number(&mut out, u64::from(trigram - previous));
+                previous = trigram;
+            }
+        }
+
+        out
+    }
+
+    fn decode(data: &[u8]) -> Option<Index> {
+        let mut data = data.strip_prefix(MAGIC)?;
+        let mut files = BTreeMap::new();
+        for _ in 0..read_number(&mut data)? {
+            let len = read_number(&mut data)? as usize;
+            let path = String::from_utf8(data.get(..len)?.to_vec()).ok()?;
+            data = &data[len..];
+            let stamp = Stamp {
+                modified_secs: read_number(&mut data)?,
+                modified_nanos: u32::try_from(read_number(&mut data)?).ok()?,
+                len: read_number(&mut data)?,
+            };
+            let mut trigrams = Vec::new();
+            let mut previous = 0u32;
+            for _ in 0..read_number(&mut data)? {
+                previous = previous.checked_add(u32::try_from(read_number(&mut data)?).ok()?)?;
+                trigrams.push(previous);
+            }
+            files.insert(path, Entry { stamp, trigrams });
+        }
+
+        Some(Index { files })
+    }
+}
+
+// The trigrams that any file containing one of `queries` must have, per query.  None when one of
+// them is too short to have any, since it could be anywhere
+pub fn query_trigrams(queries: &[String]) -> Option<Vec<Vec<u32>>> {
+    queries.iter()
+        .map(|query| {
+            let trigrams = trigrams(query);
+            if trigrams.is_empty() {
+                None
+            } else {
+                Some(trigrams)
+            }
+        })
+        .collect()
+}
+
+// The sorted set of trigrams in `text` once folded, each packed into the low 24 bits of a u32
+fn trigrams(text: &str) -> Vec<u32> {
+    let mut trigrams: Vec<u32> = unicode::fold(text)
+        .as_bytes()
+        .windows(3)
+        .map(|w| u32::from(w[0]) << 16 | u32::from(w[1]) << 8 | u32::from(w[2]))
+        .collect();
+    trigrams.sort_unstable();
+    trigrams.dedup();
+    trigrams
+}
+
+// Paths are stored relative to the indexed directory with `/` between components
+fn key(root: &Path, path: &Path) -> Option<String> {
+    let relative = path.strip_prefix(root).ok()?;
+    let parts: Option<Vec<&str>> = relative.iter().map(|part| part.to_str()).collect();
+    Some(parts?.join("/"))
+}
+
+// Numbers are written 7 bits at a time, with the top bit set on every byte but the last
+fn write_number(out: &mut Vec<u8>, mut n: u64) {
+    while n >= 0x80 {
+        out.push((n as u8 & 0x7f) | 0x80);
+        n >>= 7;
+    }
+    out.push(n as u8);
+}
+
+fn read_number(data: &mut &[u8]) -> Option<u64> {
+    let mut n = 0u64;
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = data.split_first()?;
+        *data = rest;
+        n |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Some(n);
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tree() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("src")).unwrap();
+        fs::write(dir.path().join("src/main.rs"), "fn main() {\n    Config::new();\n}\n").unwrap();
+        fs::write(dir.path().join("src/lib.rs"), "pub struct Config;\n").unwrap();
+        fs::write(dir.path().join("README"), "nothing to see here\n").unwrap();
+        fs::write(dir.path().join("STREETS"), "Hauptstraße\n").unwrap();
+        dir
+    }
+
+    fn candidates(root: &Path, queries: &[&str]) -> Vec<String> {
+        let index = open(root).unwrap();
+        let queries: Vec<String> = queries.iter().map(|query| query.to_string()).collect();
+        let queries = query_trigrams(&queries).unwrap();
+        walk::files(root, &WalkOptions::default()).0
+            .into_iter()
+            .filter(|path| index.may_contain(root, path, &queries))
+            .map(|path| key(root, &path).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn narrows_candidates() {
+        let dir = tree();
+        build(dir.path()).unwrap();
+
+        assert_eq!(vec!["src/lib.rs", "src/main.rs"], candidates(dir.path(), &["config"]));
+        assert_eq!(vec!["src/main.rs"], candidates(dir.path(), &["fn main"]));
+        assert_eq!(vec!["README", "src/main.rs"], candidates(dir.path(), &["main(", "see"]));
+        assert!(candidates(dir.path(), &["frobnicate"]).is_empty());
+    }
+
+    #[test]
+    fn stale_files_are_always_candidates() {
+        let dir = tree();
+        build(dir.path()).unwrap();
+        fs::write(dir.path().join("README"), "frobnicate the config\n").unwrap();
+
+        assert_eq!(vec!["README"], candidates(dir.path(), &["frobnicate"]));
+    }
+
+    #[test]
+    fn incremental_rebuild() {
+        let dir = tree();
+        let stats = build(dir.path()).unwrap();
+        assert_eq!((4, 4, 0), (stats.files, stats.updated, stats.removed));
+
+        fs::write(dir.path().join("README"), "frobnicate\n").unwrap();
+        fs::remove_file(dir.path().join("src/lib.rs")).unwrap();
+        fs::write(dir.path().join("src/new.rs"), "mod new;\n").unwrap();
+        let stats = build(dir.path()).unwrap();
+        assert_eq!((4, 2, 1), (stats.files, stats.updated, stats.removed));
+        assert_eq!(vec!["README"], candidates(dir.path(), &["frobnicate"]));
+    }
+
+    #[test]
+    fn unreadable_files_are_left_out() {
+        let dir = tree();
+        build(dir.path()).unwrap();
+        let (mut paths, _) = walk::files(dir.path(), &WalkOptions::default());
+        // Gone since the walk found them: one that was indexed before and one that wasn't
+        fs::remove_file(dir.path().join("src/lib.rs")).unwrap();
+        paths.push(dir.path().join("src/new.rs"));
+
+        let stats = index_files(dir.path(), paths).unwrap();
+        assert_eq!((3, 0, 1), (stats.files, stats.updated, stats.removed));
+        assert_eq!(3, open(dir.path()).unwrap().files.len());
+    }
+
+    #[test]
+    fn case_is_folded() {
+        let dir = tree();
+        build(dir.path()).unwrap();
+
+        assert_eq!(vec!["STREETS"], candidates(dir.path(), &["STRASSE"]));
+        assert_eq!(vec!["README"], candidates(dir.path(), &["NOTHING"]));
+    }
+
+    #[test]
+    fn short_queries_could_be_anywhere() {
+        assert!(query_trigrams(&["ab".to_string()]).is_none());
+        assert!(query_trigrams(&["abc".to_string(), "d".to_string()]).is_none());
+        assert!(query_trigrams(&["abc".to_string()]).is_some());
+    }
+
+    #[test]
+    fn round_trip() {
+        let dir = tree();
+        build(dir.path()).unwrap();
+        let data = fs::read(dir.path().join(FILE_NAME)).unwrap();
+
+        assert_eq!(data, Index::decode(&data).unwrap().encode());
+        assert!(Index::decode(&data[..data.len() - 1]).is_none());
+        assert!(Index::decode(b"something else").is_none());
+    }
+}
@@ -0,0 +1,141 @@
+// `--json` output: one JSON object per line so scripts don't have to pick apart `path:line:text`
+// Every record looks like `{"type": ..., "data": {...}}`.  A file with results is wrapped in a
+// `begin` and an `end` record with its `match` and `context` records in between, and a single
+// `summary` record closes the run.  Offsets are in bytes: `absolute_offset` is where the line
+// starts in the file and each submatch's `start`/`end` are relative to the start of the line
+use std::io::{self, Write};
+use std::ops::Range;
+use std::path::Path;
+
+use serde_json::{json, Value};
+
+use crate::{ContextLine, Match};
+
+fn write_record(out: &mut impl Write, kind: &str, data: Value) -> io::Result<()> {
+    serde_json::to_writer(&mut *out, &json!({ "type": kind, "data": data }))?;
+    writeln!(out)
+}
+
+fn path_value(path: &Path) -> Value {
+    Value::String(path.to_string_lossy().into_owned())
+}
+
+pub fn write_begin(out: &mut impl Write, path: &Path) -> io::Result<()> {
+    write_record(out, "begin", json!({ "path": path_value(path) }))
+}
+
+// `edits` is how many edits away from the query the line is, with `--fuzzy`
+pub fn write_match(
+    out: &mut impl Write,
+    path: &Path,
+    m: &Match,
+    submatches: &[Range<usize>],
+    edits: Option<usize>,
+) -> io::Result<()> {
+    let submatches: Vec<Value> = submatches.iter()
+        .map(|range| json!({
+            "match": &m.line[range.clone()],
+            "start": range.start,
+            "end": range.end,
+        }))
+        .collect();
+
+    let mut data = json!({
+        "path": path_value(path),
+        "line_number": m.line_number,
+        "absolute_offset": m.byte_offset,
+        "line": m.line,
+        "submatches": submatches,
+    });
+    if let Some(edits) = edits {
+        data["edits"] = json!(edits);
+    }
+    write_record(out, "match", data)
+}
+
+pub fn write_context(out: &mut impl Write, path: &Path, line: &ContextLine) -> io::Result<()> {
+    write_record(out, "context", json!({
+        "path": path_value(path),
+        "line_number": line.line_number,
+        "absolute_offset": line.byte_offset,
+        "line": line.line,
+    }))
+}
+
+pub fn write_end(out: &mut impl Write, path: &Path, matched_lines: usize) -> io::Result<()> {
+    write_record(out, "end", json!({ "path": path_value(path), "matched_lines": matched_lines }))
+}
+
+// Totals for the whole run
+pub fn write_summary(
+    out: &mut impl Write,
+    searches: usize,
+    searches_with_match: usize,
+    matched_lines: usize,
+) -> io::Result<()> {
+    write_record(out, "summary", json!({
+        "searches": searches,
+        "searches_with_match": searches_with_match,
+        "matched_lines": matched_lines,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn records(write: impl FnOnce(&mut Vec<u8>) -> io::Result<()>) -> Vec<Value> {
+        let mut out = Vec::new();
+        write(&mut out).unwrap();
+        String::from_utf8(out).unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn match_record() {
+        let m = Match { line_number: 3, byte_offset: 42, column: 5, line: "let \"x\" = x;" };
+        let written = records(|out| write_match(out, Path::new("a.rs"), &m, &[5..6, 10..11], None));
+
+        assert_eq!(
+            vec![json!({
+                "type": "match",
+                "data": {
+                    "path": "a.rs",
+                    "line_number": 3,
+                    "absolute_offset": 42,
+                    "line": "let \"x\" = x;",
+                    "submatches": [
+                        { "match": "x", "start": 5, "end": 6 },
+                        { "match": "x", "start": 10, "end": 11 },
+                    ],
+                },
+            })],
+            written
+            );
+    }
+
+    #[test]
+    fn fuzzy_match_record() {
+        let m = Match { line_number: 1, byte_offset: 0, column: 1, line: "nobdy" };
+        let written = records(|out| write_match(out, Path::new("a"), &m, &[], Some(1)));
+
+        assert_eq!(1, written[0]["data"]["edits"]);
+    }
+
+    #[test]
+    fn one_record_per_line() {
+        let line = ContextLine { line_number: 1, byte_offset: 0, line: "a\nb" };
+        let written = records(|out| {
+            write_begin(out, Path::new("a.rs"))?;
+            write_context(out, Path::new("a.rs"), &line)?;
+            write_end(out, Path::new("a.rs"), 0)?;
+            write_summary(out, 1, 0, 0)
+        });
+
+        let kinds: Vec<&str> = written.iter().map(|r| r["type"].as_str().unwrap()).collect();
+        assert_eq!(vec!["begin", "context", "end", "summary"], kinds);
+        assert_eq!("a\nb", written[1]["data"]["line"]);
+    }
+}
@@ -0,0 +1,351 @@
+// `--code-only` and `--comments-only`: telling code from comments and string literals
+// This is a tokenizer in the loosest sense: all it knows about a language is how its comments
+// and string literals start and end, which is enough to say which of the three any byte of a
+// source file is in.  Block comments and some strings span lines, so a Tokenizer is fed every
+// line of a file in order and carries what it was in from one line to the next.  Getting it
+// wrong (eg: a JavaScript regex literal containing a quote) only misclassifies the rest of that
+// line or string, never the whole file
+use std::path::Path;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Language {
+    Rust,
+    C,
+    Python,
+    JavaScript,
+}
+
+impl Language {
+    // The language of a file, going by its extension
+    pub fn from_path(path: &Path) -> Option<Language> {
+        let extension = path.extension()?.to_str()?;
+        match extension {
+            "rs" => Some(Language::Rust),
+            "c" | "h" | "cc" | "cpp" | "cxx" | "hh" | "hpp" => Some(Language::C),
+            "py" | "pyi" => Some(Language::Python),
+            "js" | "mjs" | "cjs" | "jsx" => Some(Language::JavaScript),
+            _ => None,
+        }
+    }
+
+    fn line_comment(self) -> &'static [u8] {
+        match self {
+            Language::Python => b"#",
+            Language::Rust | Language::C | Language::JavaScript => b"//",
+        }
+    }
+
+    fn block_comment(self) -> Option<(&'static [u8], &'static [u8])> {
+        match self {
+            Language::Python => None,
+            Language::Rust | Language::C | Language::JavaScript => Some((b"/*", b"*/")),
+        }
+    }
+
+    // The string literal starting at `i`, if there is one
+    fn string_at(self, line: &[u8], i: usize) -> Option<Literal> {
+        let quote = |len: usize, close: &[u8], multiline: bool| Literal {
+            open: len,
+            close: close.to_vec(),
+            escapes: true,
+            multiline,
+        };
+        match (self, line[i]) {
+            // Rust strings can span lines, and there are raw strings and char literals to tell
+            // apart from lifetimes
+            (Language::Rust, b'"') => Some(quote(1, b"\"", true)),
+            // The prefix of a raw string is never the end of an identifier like `bar"` would be
+            (Language::Rust, b'r' | b'b' | b'c') if i == 0 || !is_identifier(line[i - 1]) => {
+                raw_string(line, i)
+            }
+            (Language::Rust, b'\'') => {
+                char_literal(&line[i + 1..]).then(|| quote(1, b"'", false))
+            }
+            (Language::C, b'"' | b'\'') => Some(quote(1, &line[i..=i], false)),
+            (Language::Python, b'"' | b'\'') => {
+                if line[i..].starts_with(b"\"\"\"") || line[i..].starts_with(b"'''") {
+                    Some(quote(3, &line[i..i + 3], true))
+                } else {
+                    Some(quote(1, &line[i..=i], false))
+                }
+            }
+            (Language::JavaScript, b'`') => Some(quote(1, b"`", true)),
+            (Language::JavaScript, b'"' | b'\'') => Some(quote(1, &line[i..=i], false)),
+            _ => None,
+        }
+    }
+}
+
+// What a part of a line is
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Region {
+    Code,
+    Comment,
+    String,
+}
+
+// The regions of one line, as the offsets at which each one starts
+#[derive(Debug, PartialEq)]
+pub struct Regions(Vec<(usize, Region)>);
+
+impl Regions {
+    pub fn at(&self, offset: usize) -> Region {
+        let index = self.0.partition_point(|&(start, _)| start <= offset);
+        self.0[index.saturating_sub(1)].1
+    }
+
+    fn mark(&mut self, start: usize, region: Region) {
+        match self.0.last_mut() {
+            Some(last) if last.0 == start => last.1 = region,
+            Some(last) if last.1 == region => {}
+            _ => self.0.push((start, region)),
+        }
+    }
+}
+
+// How a string literal ends
+struct Literal {
+    // The length of its opening quote (and any prefix)
+    open: usize,
+    close: Vec<u8>,
+    // Whether a backslash escapes the next character
+    escapes: bool,
+    // Whether it carries on past the end of the line without a backslash
+    multiline: bool,
+}
+
+enum State {
+    Code,
+    // Rust's block comments nest, so we count how deep we are
+    Comment(usize),
+    String(Literal),
+}
+
+pub struct Tokenizer {
+    language: Language,
+    state: State,
+}
+
+impl Tokenizer {
+    pub fn new(language: Language) -> Tokenizer {
+        Tokenizer { language, state: State::Code }
+    }
+
+    // Classify `line`, which has to be the line after the one classified last
+    pub fn classify(&mut self, line: &str) -> Regions {
+        let line = line.as_bytes();
+        let mut regions = Regions(Vec::new());
+        let mut i = 0;
+        regions.mark(0, self.region());
+        while i < line.len() {
+            let rest = &line[i..];
+            match &mut self.state {
+                State::Comment(depth) => {
+                    let (open, close) = self.language.block_comment().unwrap_or((b"", b""));
+                    if self.language == Language::Rust && rest.starts_with(open) {
+                        *depth += 1;
+                        i += open.len();
+                    } else if rest.starts_with(close) {
+                        *depth -= 1;
+                        i += close.len();
+                        if *depth == 0 {
+                            self.state = State::Code;
+                            regions.mark(i, Region::Code);
+                        }
+                    } else {
+                        i += 1;
+                    }
+                }
+                State::String(literal) => {
+                    if literal.escapes && rest[0] == b'\\' {
+                        i += 2;
+                    } else if rest.starts_with(&literal.close) {
+                        i += literal.close.len();
+                        self.state = State::Code;
+                        regions.mark(i, Region::Code);
+                    } else {
+                        i += 1;
+                    }
+                }
+                State::Code => {
+                    if rest.starts_with(self.language.line_comment()) {
+                        regions.mark(i, Region::Comment);
+                        break;
+                    }
+                    if let Some((open, _)) = self.language.block_comment() {
+                        if rest.starts_with(open) {
+                            regions.mark(i, Region::Comment);
+                            self.state = State::Comment(1);
+                            i += open.len();
+                            continue;
+                        }
+                    }
+                    match self.language.string_at(line, i) {
+                        Some(literal) => {
+                            regions.mark(i, Region::String);
+                            i += literal.open;
+                            self.state = State::String(literal);
+                        }
+                        None => i += 1,
+                    }
+                }
+            }
+        }
+
+        // A string that can't span lines ends with the line, unless the line ending is escaped
+        if let State::String(literal) = &self.state {
+            let continued = literal.escapes && line.ends_with(b"\\");
+            if !literal.multiline && !continued {
+                self.state = State::Code;
+            }
+        }
+        regions
+    }
+
+    fn region(&self) -> Region {
+        match self.state {
+            State::Code => Region::Code,
+            State::Comment(_) => Region::Comment,
+            State::String(_) => Region::String,
+        }
+    }
+}
+
+fn is_identifier(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || byte == b'_'
+}
+
+// Rust's raw strings (`r"..."`, `br#"..."#` and friends), which end at a quote followed by as many
+// `#` as they started with and have no escapes.  Also plain byte and C strings (`b"..."`)
+fn raw_string(line: &[u8], i: usize) -> Option<Literal> {
+    let mut j = i;
+    if matches!(line[j], b'b' | b'c') {
+        j += 1;
+    }
+    let raw = line.get(j) == Some(&b'r');
+    if raw {
+        j += 1;
+    }
+    let hashes = line[j..].iter().take_while(|&&byte| byte == b'#').count();
+    if line.get(j + hashes) != Some(&b'"') || (hashes > 0 && !raw) || j == i {
+        return None;
+    }
+
+    let mut close = vec![b'"'];
+    close.resize(1 + hashes, b'#');
+    Some(Literal { open: j + hashes + 1 - i, close, escapes: !raw, multiline: true })
+}
+
+// Whether the quote just before `rest` starts a char literal rather than a lifetime or label:
+// either an escape, or a single character and then a closing quote
+fn char_literal(rest: &[u8]) -> bool {
+    if rest.first() == Some(&b'\\') {
+        return true;
+    }
+    // The character may take several bytes
+    let text = String::from_utf8_lossy(&rest[..rest.len().min(5)]);
+    let mut chars = text.chars();
+    chars.next().is_some() && chars.next() == Some('\'')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Each line of `source` as its text with the regions written in: `<...>` around comments and
+    // `[...]` around strings
+    fn render(language: Language, source: &str) -> Vec<String> {
+        let mut tokenizer = Tokenizer::new(language);
+        source.lines()
+            .map(|line| {
+                let regions = tokenizer.classify(line);
+                let mut rendered = String::new();
+                let mut region = Region::Code;
+                for (offset, c) in line.char_indices() {
+                    let next = regions.at(offset);
+                    if next != region {
+                        rendered.push_str(match region {
+                            Region::Comment => ">",
+                            Region::String => "]",
+                            Region::Code => "",
+                        });
+                        rendered.push_str(match next {
+                            Region::Comment => "<",
+                            Region::String => "[",
+                            Region::Code => "",
+                        });
+                        region = next;
+                    }
+                    rendered.push(c);
+                }
+                rendered.push_str(match region {
+                    Region::Comment => ">",
+                    Region::String => "]",
+                    Region::Code => "",
+                });
+                rendered
+            })
+            .collect()
+    }
+
+    #[test]
+    fn rust() {
+        let source = r###"let s = "a // b"; // note
+/* outer /* inner */ still */ x
+let r = r#"raw "quoted" \"#; let c = '"'; fn f<'a>(x: &'a str) {}
+let multi = "one
+two"; b'x'"###;
+
+        assert_eq!(
+            vec![
+                r#"let s = ["a // b"]; <// note>"#,
+                "</* outer /* inner */ still */> x",
+                r##"let r = [r#"raw "quoted" \"#]; let c = ['"']; fn f<'a>(x: &'a str) {}"##,
+                r#"let multi = ["one]"#,
+                r#"[two"]; b['x']"#,
+            ],
+            render(Language::Rust, source)
+        );
+    }
+
+    #[test]
+    fn c() {
+        let source = "char *s = \"/* not */\"; /* yes\nstill */ int x = '\\'';";
+
+        assert_eq!(
+            vec![r#"char *s = ["/* not */"]; </* yes>"#, r"<still */> int x = ['\''];"],
+            render(Language::C, source)
+        );
+    }
+
+    #[test]
+    fn python() {
+        let source = "x = 'it''s' # comment\ndoc = \"\"\"spans\n# not a comment\n\"\"\" + \"#\"";
+
+        assert_eq!(
+            vec![
+                "x = ['it''s'] <# comment>",
+                r#"doc = ["""spans]"#,
+                "[# not a comment]",
+                r##"["""] + ["#"]"##,
+            ],
+            render(Language::Python, source)
+        );
+    }
+
+    #[test]
+    fn javascript() {
+        let source = "const t = `a\n// b`; // c";
+
+        assert_eq!(vec!["const t = [`a]", "[// b`]; <// c>"], render(Language::JavaScript, source));
+    }
+
+    #[test]
+    fn languages_by_extension() {
+        assert_eq!(Some(Language::Rust), Language::from_path(Path::new("src/lib.rs")));
+        assert_eq!(Some(Language::C), Language::from_path(Path::new("a.h")));
+        assert_eq!(Some(Language::JavaScript), Language::from_path(Path::new("app.mjs")));
+        assert_eq!(None, Language::from_path(Path::new("README.md")));
+        assert_eq!(None, Language::from_path(Path::new("Makefile")));
+    }
+}
@@ -1,91 +1,1303 @@
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::HashMap;
 // Bring in the standard library module to handle Error types
 use std::error::Error;
 // Bring in the standard library module to handle files
-use std::fs;
+use std::fs::{self, File};
 //
 use std::env;
+use std::io::{self, BufRead, BufReader, IsTerminal, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use aho_corasick::{AhoCorasick, MatchKind};
+use regex::{Regex, RegexBuilder};
+
+mod cli;
+mod context;
+mod decompress;
+mod fields;
+mod fuzzy;
+mod index;
+mod json;
+mod lang;
+mod output;
+mod parallel;
+mod replace;
+mod searcher;
+mod settings;
+mod unicode;
+mod walk;
+mod watch;
+
+pub use cli::{help, ArgsError};
+pub use context::{ContextLine, ContextOptions, Event};
+pub use fields::Field;
+pub use index::{build as build_index, IndexStats};
+pub use lang::Region;
+pub use output::{ColorChoice, OutputOptions, Palette};
+pub use searcher::{MatchRecord, Matches, SearchError, Searcher, SearcherBuilder};
+pub use walk::{GlobFilter, WalkOptions};
 
 pub struct Config {
-    pub query: String,
-    pub filename: String,
+    // What to search for: the QUERY argument, or everything given with -e and -f
+    pub patterns: Vec<String>,
+    pub filenames: Vec<String>,
     pub case_sensitive: bool,
+    pub matcher: Matcher,
+    pub mode: Mode,
+    pub walk: WalkOptions,
+    pub output: OutputOptions,
+    pub context: ContextOptions,
+    // `-j`: how many files to search at once
+    pub jobs: usize,
+    pub color: ColorChoice,
+    pub binary: BinaryMode,
+    // `-z`: search the contents of compressed files
+    pub decompress: bool,
+    // `--replace`: what to print (or with `--in-place`, write) instead of each match
+    pub replace: Option<String>,
+    pub in_place: bool,
+    // `--dry-run`: show what `--in-place` would change as a diff instead
+    pub dry_run: bool,
+    // `-U`: match against whole files so matches can span lines
+    pub multiline: bool,
+    // `--code-only` or `--comments-only`: only select lines with a match in this kind of region
+    pub only: Option<Region>,
+    // `--field` or `--csv-column`: match against this field of each record instead of the line
+    pub field: Option<Field>,
+    // `-m`: stop reading a file after this many selected lines, or with `-U` this many matches
+    pub max_count: Option<usize>,
+    pub sort: SortBy,
+    // `--unique`: print each distinct selected line of a file once, with how often it was selected
+    pub unique: bool,
+    // `--watch`: keep searching files as they change
+    pub watch: bool,
+    // Use a directory's trigram index to skip files that can't match; off with `--no-index`
+    pub use_index: bool,
 }
 
-// The error type for Result is &'static str as this is the type of string literals which is what
-// is being returned in this program
-impl Config {
-    // Remember that a ' denotes a lifetime specifier and that static is the longest
-    // lifetime annotation in Rust - the duration of an entire program!
-    pub fn new(mut args: std::env::Args) -> Result<Config, &'static str> {
-        // Skip the 0th index containing calling context
-        args.next();
-
-        // Returning an err here is more appropriate than invoking
-        // panic! as this is a usage error rather than a programming
-        // problem
-        let query = match args.next() {
-            Some(arg) => arg,
-            None => return Err("Didn't get a query string"),
+// What to print for each file searched
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Mode {
+    // The selected lines themselves (the default)
+    #[default]
+    Lines,
+    // `-c`: just the number of selected lines
+    Count,
+    // `-l`: just the name of the file, if it has any selected line
+    FilesWithMatches,
+    // `-L`: just the name of the file, if it has no selected line
+    FilesWithoutMatch,
+}
+
+// What to do with files that look binary, ie: have a NUL byte near the start
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum BinaryMode {
+    // Skip binary files found while walking a directory, and for files that were named on the
+    // command line print "Binary file ... matches" instead of their lines (the default)
+    #[default]
+    Auto,
+    // `--binary`: report every binary file that matches, wherever it came from
+    Report,
+    // `-a`/`--text`: search binary files as if they were text
+    Text,
+}
+
+// The order files are searched and printed in
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum SortBy {
+    // The order they were named in, with the files in each directory sorted by name (the default)
+    #[default]
+    None,
+    // `--sort path`: every file by its path, wherever it came from
+    Path,
+    // `--sort mtime`: least recently modified first, so the newest files are printed last
+    Modified,
+}
+
+impl SortBy {
+    pub fn parse(by: &str) -> Option<SortBy> {
+        match by {
+            "none" => Some(SortBy::None),
+            "path" => Some(SortBy::Path),
+            "mtime" => Some(SortBy::Modified),
+            _ => None,
+        }
+    }
+}
+
+// A single matching line along with where it was found
+// `line_number` and `column` count from 1 like editors do, while `byte_offset` is the 0-based
+// position of the start of the line within the searched contents
+#[derive(Debug, PartialEq)]
+pub struct Match<'a> {
+    pub line_number: usize,
+    pub byte_offset: usize,
+    pub column: usize,
+    pub line: &'a str,
+}
+
+// A line selected by `search_multiline`, along with the parts of it that matched
+pub type MultilineMatch<'a> = (Match<'a>, Vec<Range<usize>>);
+
+// A Matcher decides whether a line contains one of the queries.  Regular expressions are driven by
+// the regex engine, with several of them combined into one alternation.  Plain queries are all
+// looked for in a single pass over the line with Aho-Corasick, so searching for hundreds of
+// identifiers at once costs about the same as searching for one.  Plain queries that ignore case
+// are compared using full Unicode case folding, see `unicode`
+pub struct Matcher {
+    engine: Engine,
+    whole_word: bool,
+    // With `-w`, the regex wrapped in `\b(?:...)\b`.  It wins when both find a word at the same
+    // place because it lets the regex engine pick `rusty` over `rust` for `rust|rusty`, but the
+    // plain regex is searched with too since `\b` disagrees with `unicode::is_whole_word` about
+    // some words (eg: `$x`)
+    words: Option<Regex>,
+    invert: bool,
+    // Whether replacement templates can refer to capture groups, ie: in regex mode
+    expand: bool,
+}
+
+enum Engine {
+    Regex(Regex),
+    Literals(AhoCorasick),
+    // The queries with their case folded, to be found in the folded line
+    Folded(AhoCorasick),
+    Fuzzy(fuzzy::Fuzzy),
+}
+
+// The knobs that change how a query is matched
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MatcherOptions {
+    // Treat the query as a regular expression rather than a plain string
+    pub regex: bool,
+    pub ignore_case: bool,
+    // `-w`: only match where the query is a whole word
+    pub whole_word: bool,
+    // `-v`: select the lines that *don't* match
+    pub invert: bool,
+    // `--fuzzy`: how many edits a match may be away from the query
+    pub fuzzy: Option<usize>,
+    // `-U`: let `^` and `$` match at the start and end of every line of a multiline search
+    pub multiline: bool,
+}
+
+impl Matcher {
+    pub fn new(query: &str, options: &MatcherOptions) -> Result<Matcher, regex::Error> {
+        Matcher::with_patterns(&[query], options)
+    }
+
+    // Build a matcher that selects lines matching any of `patterns` (`-e` and `-f`).  With no
+    // patterns at all nothing matches
+    pub fn with_patterns<S: AsRef<str>>(
+        patterns: &[S],
+        options: &MatcherOptions,
+    ) -> Result<Matcher, regex::Error> {
+        let patterns: Vec<&str> = patterns.iter().map(AsRef::as_ref).collect();
+        let engine = if let Some(max_edits) = options.fuzzy {
+            Engine::Fuzzy(fuzzy::Fuzzy::new(&patterns, max_edits, options.ignore_case))
+        } else if options.regex && !patterns.is_empty() {
+            // Each pattern gets its own group so an alternation inside one doesn't swallow its
+            // neighbours.  Capture groups are numbered across all of them
+            let alternation = patterns.iter()
+                .map(|pattern| format!("(?:{})", pattern))
+                .collect::<Vec<_>>()
+                .join("|");
+            Engine::Regex(build_regex(&alternation, options)?)
+        } else if options.ignore_case {
+            let folded: Vec<String> = patterns.iter().map(|query| unicode::fold(query)).collect();
+            match build_literals(&folded) {
+                Some(literals) => Engine::Folded(literals),
+                None => Engine::Regex(build_regex(&escape_all(&patterns), options)?),
+            }
+        } else {
+            match build_literals(&patterns) {
+                Some(literals) => Engine::Literals(literals),
+                None => Engine::Regex(build_regex(&escape_all(&patterns), options)?),
+            }
         };
 
-        let filename = match args.next() {
-            Some(arg) => arg,
-            None => return Err("Didn't get a file name"),
+        let words = match &engine {
+            // The group keeps an alternation like `a|b` from escaping the word boundaries
+            Engine::Regex(regex) if options.whole_word => {
+                Some(build_regex(&format!(r"\b(?:{})\b", regex.as_str()), options)?)
+            }
+            _ => None,
         };
 
-        
-        let case_sensitive = env::var("CASE_INSENSITIVE").is_err();
+        Ok(Matcher {
+            engine,
+            whole_word: options.whole_word,
+            words,
+            invert: options.invert,
+            expand: options.regex,
+        })
+    }
+
+    // Build a matcher that looks for `query` as a plain substring
+    pub fn literal(query: &str, case_sensitive: bool) -> Result<Matcher, regex::Error> {
+        let options = MatcherOptions { ignore_case: !case_sensitive, ..MatcherOptions::default() };
+        Matcher::new(query, &options)
+    }
+
+    // Build a matcher that treats `pattern` as a regular expression (eg: `^fn\s+\w+`)
+    pub fn regex(pattern: &str, case_sensitive: bool) -> Result<Matcher, regex::Error> {
+        let options = MatcherOptions {
+            regex: true,
+            ignore_case: !case_sensitive,
+            ..MatcherOptions::default()
+        };
+        Matcher::new(pattern, &options)
+    }
+
+    // Whether `line` should be printed, taking `-v` into account
+    pub fn is_match(&self, line: &str) -> bool {
+        self.find(line).is_some() != self.invert
+    }
+
+    // The byte range of the first match in `line`, if there is one.  This ignores `-v`
+    pub fn find(&self, line: &str) -> Option<Range<usize>> {
+        self.find_from(line, self.fold_line(line).as_ref(), 0).map(|(range, _)| range)
+    }
+
+    // The byte ranges of every match in `line`
+    pub fn find_all(&self, line: &str) -> Vec<Range<usize>> {
+        self.matches(line).into_iter().map(|(range, _)| range).collect()
+    }
+
+    // With `--fuzzy`, the fewest edits any match in `line` needs.  Exact matches need none
+    pub fn distance(&self, line: &str) -> Option<usize> {
+        self.matches(line).into_iter().map(|(_, edits)| edits).min()
+    }
+
+    pub fn is_fuzzy(&self) -> bool {
+        matches!(self.engine, Engine::Fuzzy(_))
+    }
+
+    // Whether every selected line contains one of the queries as given, or with its case folded.
+    // That is what lets the trigram index rule files out
+    fn selects_substrings(&self) -> bool {
+        matches!(self.engine, Engine::Literals(_) | Engine::Folded(_)) && !self.invert
+    }
+
+    // Every match in `line` along with how many edits it needed
+    fn matches(&self, line: &str) -> Vec<(Range<usize>, usize)> {
+        let folded = self.fold_line(line);
+        let mut found = Vec::new();
+        let mut at = 0;
+        while let Some((range, edits)) = self.find_from(line, folded.as_ref(), at) {
+            at = if range.is_empty() { next_char(line, range.end) } else { range.end };
+            found.push((range, edits));
+        }
+
+        found
+    }
+
+    // `line` with every match replaced by `template`, and the byte ranges the replacements ended
+    // up at in the result.  In regex mode the template can refer to capture groups as `$1` or
+    // `${name}` (and `$$` is a plain `$`); a plain query's template is used as it is
+    pub fn replace(&self, line: &str, template: &str) -> (String, Vec<Range<usize>>) {
+        let mut replaced = String::with_capacity(line.len());
+        let mut ranges = Vec::new();
+        let mut last = 0;
+        for found in self.find_all(line) {
+            replaced.push_str(&line[last..found.start]);
+            let start = replaced.len();
+            match &self.engine {
+                Engine::Regex(regex) if self.expand => {
+                    // A match is the same wherever the search for it started, so this finds it
+                    // again along with its groups, with whichever regex found it
+                    let captures = self.words.iter()
+                        .chain([regex])
+                        .filter_map(|regex| regex.captures_at(line, found.start))
+                        .find(|captures| captures.get(0).map(|m| m.range()) == Some(found.clone()));
+                    if let Some(captures) = captures {
+                        captures.expand(template, &mut replaced);
+                    }
+                }
+                _ => replaced.push_str(template),
+            }
+            ranges.push(start..replaced.len());
+            last = found.end;
+        }
+        replaced.push_str(&line[last..]);
+
+        (replaced, ranges)
+    }
+
+    fn fold_line(&self, line: &str) -> Option<unicode::FoldedLine> {
+        match self.engine {
+            Engine::Folded(_) => Some(unicode::FoldedLine::new(line)),
+            Engine::Regex(_) | Engine::Literals(_) | Engine::Fuzzy(_) => None,
+        }
+    }
+
+    // The first match starting at or after byte `from` and the number of edits it needed (only
+    // ever non-zero with `--fuzzy`).  `folded` is `line` folded when the engine needs it
+    fn find_from(
+        &self,
+        line: &str,
+        folded: Option<&unicode::FoldedLine>,
+        from: usize,
+    ) -> Option<(Range<usize>, usize)> {
+        let bare = self.find_with(line, folded, from, None);
+        let words = self.words.as_ref()
+            .and_then(|words| self.find_with(line, folded, from, Some(words)));
+        // Whichever starts first, so neither regex skips a word the other one finds
+        match (words, bare) {
+            (Some(words), Some(bare)) if bare.0.start < words.0.start => Some(bare),
+            (Some(words), _) => Some(words),
+            (None, bare) => bare,
+        }
+    }
+
+    // `find_from` using `regex` in place of the engine's own regex, if it's given
+    fn find_with(
+        &self,
+        line: &str,
+        folded: Option<&unicode::FoldedLine>,
+        mut from: usize,
+        regex: Option<&Regex>,
+    ) -> Option<(Range<usize>, usize)> {
+        while from <= line.len() {
+            let (found, edits) = match (&self.engine, folded) {
+                (Engine::Regex(own), _) => (regex.unwrap_or(own).find_at(line, from)?.range(), 0),
+                (Engine::Literals(literals), _) => (find_literal(literals, line, from)?, 0),
+                (Engine::Folded(literals), Some(folded)) => {
+                    (folded.find(from, |folded, at| find_literal(literals, folded, at))?, 0)
+                }
+                (Engine::Folded(_), None) => unreachable!("folded engine without a folded line"),
+                (Engine::Fuzzy(fuzzy), _) => fuzzy.find_at(line, from)?,
+            };
+            if !self.whole_word || unicode::is_whole_word(line, &found) {
+                return Some((found, edits));
+            }
+            // Try again one character later: a shorter or later match may still be a word
+            from = next_char(line, found.start);
+        }
+
+        None
+    }
+
+    // Like `find` but honoring `-v`: a selected line that was picked because it *doesn't* match
+    // reports an empty range at its start
+    fn select(&self, line: &str) -> Option<Range<usize>> {
+        match (self.find(line), self.invert) {
+            (Some(found), false) => Some(found),
+            (None, true) => Some(0..0),
+            _ => None,
+        }
+    }
+
+    // The actual searching for minigrep - the heart of the program
+    // There is an explicit lifetime which specifies that the returned matches should contain
+    // string slices that reference slices of the argument `contents` rather than the query
+    // We're essentially telling the compiler the lifetime of the returned vector is the
+    // same as the lifetime of contents!  The query can be tossed after we finish searching
+    pub fn search<'a>(&self, contents: &'a str) -> Vec<Match<'a>> {
+        lines(contents)
+            .filter_map(|(line_number, byte_offset, line)| {
+                self.select(line).map(|found| Match {
+                    line_number,
+                    byte_offset,
+                    column: found.start + 1,
+                    line,
+                })
+            })
+            .collect()
+    }
+
+    // Like `search` but also returns the lines around each match, in order, with a
+    // Separator between groups of lines that aren't next to each other
+    pub fn search_with_context<'a>(
+        &self,
+        contents: &'a str,
+        context: ContextOptions,
+    ) -> Vec<Event<Match<'a>, ContextLine<'a>>> {
+        let mut tracker = context::ContextTracker::new(context);
+        let mut events = Vec::new();
+        for (line_number, byte_offset, line) in lines(contents) {
+            match self.select(line) {
+                Some(found) => {
+                    let m = Match { line_number, byte_offset, column: found.start + 1, line };
+                    tracker.push_match(line_number, m, &mut events);
+                }
+                None => {
+                    let context = || ContextLine { line_number, byte_offset, line };
+                    tracker.push_other(line_number, context, &mut events);
+                }
+            }
+        }
+
+        events
+    }
+
+    // `-U`: match against the whole of `contents` rather than a line at a time, so a match can
+    // span several lines (eg: `fn \w+\(\s*self` finds a signature split after the parenthesis).
+    // Every line a match touches is selected, along with the parts of that line the matches
+    // cover, and context is added around them as usual.  `-v` makes no sense here and isn't
+    // taken into account
+    pub fn search_multiline<'a>(
+        &self,
+        contents: &'a str,
+        context: ContextOptions,
+    ) -> Vec<Event<MultilineMatch<'a>, ContextLine<'a>>> {
+        self.search_multiline_limited(contents, context, None)
+    }
+
+    // `search_multiline` keeping only the first `max_count` matches.  `-m` counts matches rather
+    // than lines here, so every line the last of them touches is still selected
+    fn search_multiline_limited<'a>(
+        &self,
+        contents: &'a str,
+        context: ContextOptions,
+        max_count: Option<usize>,
+    ) -> Vec<Event<MultilineMatch<'a>, ContextLine<'a>>> {
+        let mut found = self.find_all(contents);
+        if let Some(max_count) = max_count {
+            found.truncate(max_count);
+        }
+        // The first match that doesn't end before the current line
+        let mut first = 0;
+        let mut tracker = context::ContextTracker::new(context);
+        let mut events = Vec::new();
+        for (line_number, byte_offset, line) in lines(contents) {
+            // Where the next line starts, ie: past this one's line ending
+            let next = contents[byte_offset..]
+                .find('\n')
+                .map_or(contents.len(), |i| byte_offset + i + 1);
+            let touching: Vec<&Range<usize>> =
+                found[first..].iter().take_while(|range| range.start < next).collect();
+            if touching.is_empty() {
+                let context = || ContextLine { line_number, byte_offset, line };
+                tracker.push_other(line_number, context, &mut events);
+            } else {
+                // The parts of the matches on this line, leaving out its line ending
+                let end = byte_offset + line.len();
+                let parts: Vec<Range<usize>> = touching.iter()
+                    .map(|range| range.start.max(byte_offset)..range.end.min(end))
+                    .filter(|part| part.start < part.end)
+                    .map(|part| part.start - byte_offset..part.end - byte_offset)
+                    .collect();
+                let column = parts.first().map_or(1, |part| part.start + 1);
+                let m = Match { line_number, byte_offset, column, line };
+                tracker.push_match(line_number, (m, parts), &mut events);
+            }
+            // An empty match at the start of the next line belongs to that line
+            while found.get(first).is_some_and(|range| {
+                range.end < next || range.end == next && !range.is_empty()
+            }) {
+                first += 1;
+            }
+        }
+
+        events
+    }
 
-        Ok(Config { query, filename, case_sensitive })
+    // The streaming version of `search_with_context`: lines are pulled from `reader` one at a
+    // time and handed to `sink` as soon as we know they should be printed.  Only the current line
+    // and at most `context.before` earlier lines are ever held in memory, so this works on pipes
+    // and on files far larger than RAM
+    // Lines that aren't valid UTF-8 are decoded lossily: invalid bytes become U+FFFD, which is
+    // also how they are printed.  `byte_offset` still counts the bytes actually read, but columns
+    // on such a line are positions in the decoded text
+    pub fn search_reader<R, F>(&self, reader: R, context: ContextOptions, sink: F) -> io::Result<()>
+    where
+        R: BufRead,
+        F: FnMut(Event<Match, ContextLine>) -> io::Result<()>,
+    {
+        self.search_lines(reader, context, None, |line| self.select(line), sink)
     }
+
+    // `search_reader` with `select` deciding which lines are selected and where they matched,
+    // for searches that need to know more than the line itself (see `--code-only`).  Reading
+    // stops once `max_count` lines were selected and the context after the last one is done
+    fn search_lines<R, S, F>(
+        &self,
+        mut reader: R,
+        context: ContextOptions,
+        max_count: Option<usize>,
+        mut select: S,
+        mut sink: F,
+    ) -> io::Result<()>
+    where
+        R: BufRead,
+        S: FnMut(&str) -> Option<Range<usize>>,
+        F: FnMut(Event<Match, ContextLine>) -> io::Result<()>,
+    {
+        let mut tracker = context::ContextTracker::new(context);
+        let mut buf = Vec::new();
+        let mut line_number = 0;
+        let mut byte_offset = 0;
+        // How many more lines may be selected, and then how many lines of context are left
+        let mut left = max_count;
+        let mut after = context.after;
+        loop {
+            if left == Some(0) {
+                if after == 0 {
+                    return Ok(());
+                }
+                after -= 1;
+            }
+            buf.clear();
+            let read = reader.read_until(b'\n', &mut buf)?;
+            if read == 0 {
+                return Ok(());
+            }
+            line_number += 1;
+
+            let decoded = String::from_utf8_lossy(&buf);
+            let line = decoded.strip_suffix('\n').unwrap_or(&decoded);
+            let line = line.strip_suffix('\r').unwrap_or(line);
+
+            let mut events = Vec::new();
+            let found = if left == Some(0) { None } else { select(line) };
+            match found {
+                Some(found) => {
+                    left = left.map(|n| n - 1);
+                    let m = Match { line_number, byte_offset, column: found.start + 1, line };
+                    tracker.push_match(line_number, m, &mut events);
+                }
+                None => {
+                    // Context lines may outlive `buf` while they wait for a match so they have
+                    // to be copied
+                    let context = || OwnedLine { line_number, byte_offset, line: line.to_string() };
+                    tracker.push_other(line_number, context, &mut events);
+                }
+            }
+
+            for event in events {
+                match event {
+                    Event::Match(m) => sink(Event::Match(m))?,
+                    Event::Context(owned) => sink(Event::Context(owned.as_context()))?,
+                    Event::Separator => sink(Event::Separator)?,
+                }
+            }
+            byte_offset += read;
+        }
+    }
+}
+
+// A context line read from a stream, which we own because the read buffer gets reused
+struct OwnedLine {
+    line_number: usize,
+    byte_offset: usize,
+    line: String,
+}
+
+// A line `--unique` prints once, where it was first selected, however many times it was
+struct UniqueLine {
+    line: OwnedLine,
+    column: usize,
+    found: Vec<Range<usize>>,
+    count: usize,
+}
+
+impl OwnedLine {
+    fn as_context(&self) -> ContextLine<'_> {
+        ContextLine { line_number: self.line_number, byte_offset: self.byte_offset, line: &self.line }
+    }
+}
+
+fn build_regex(pattern: &str, options: &MatcherOptions) -> Result<Regex, regex::Error> {
+    // The regex engine does its own (simple) case folding for regular expressions
+    RegexBuilder::new(pattern)
+        .case_insensitive(options.ignore_case)
+        .multi_line(options.multiline)
+        .crlf(options.multiline)
+        .build()
+}
+
+// An automaton for plain queries.  Like grep, of the queries that match at the same place the
+// longest one wins.  Building it only fails for enormous sets of queries, which are then left to
+// the regex engine instead
+fn build_literals<S: AsRef<[u8]>>(queries: &[S]) -> Option<AhoCorasick> {
+    AhoCorasick::builder()
+        .match_kind(MatchKind::LeftmostLongest)
+        .build(queries)
+        .ok()
+}
+
+fn escape_all(queries: &[&str]) -> String {
+    queries.iter().map(|query| regex::escape(query)).collect::<Vec<_>>().join("|")
 }
+
+fn find_literal(literals: &AhoCorasick, haystack: &str, from: usize) -> Option<Range<usize>> {
+    let input = aho_corasick::Input::new(haystack).span(from..haystack.len());
+    literals.find(input).map(|m| m.range())
+}
+
+// The offset of the character after the one starting at `offset`, or just past the end of `line`
+fn next_char(line: &str, offset: usize) -> usize {
+    offset + line[offset..].chars().next().map_or(1, char::len_utf8)
+}
+
+// Like str::lines() but also yields the 1-based line number and the byte offset at which each line
+// starts.  Line endings (`\n` or `\r\n`) are stripped from the yielded line
+fn lines(contents: &str) -> impl Iterator<Item = (usize, usize, &str)> {
+    let mut offset = 0;
+    contents.split_inclusive('\n')
+        .enumerate()
+        .map(move |(index, raw)| {
+            let start = offset;
+            offset += raw.len();
+            let line = raw.strip_suffix('\n').unwrap_or(raw);
+            let line = line.strip_suffix('\r').unwrap_or(line);
+            (index + 1, start, line)
+        })
+}
+
+impl Config {
+    // Taking any iterator of Strings rather than std::env::Args specifically means tests can
+    // hand in a vector of arguments
+    // Usage errors come back as an ArgsError; see cli.rs for the flags we understand
+    // Configuration files are read first unless `--no-config` is among the flags, see settings.rs
+    pub fn new(args: impl Iterator<Item = String>) -> Result<Config, ArgsError> {
+        let args: Vec<String> = args.collect();
+        let flags = args.iter().skip(1).take_while(|arg| *arg != "--");
+        let env_ignore_case = env::var("CASE_INSENSITIVE").is_ok();
+        let settings = if flags.clone().any(|arg| arg == "--no-config") {
+            settings::Settings::default()
+        } else {
+            match settings::Settings::load() {
+                Ok(settings) => settings,
+                // `--help` and `--version` have to work with a broken configuration file, not
+                // least because the error says to try `--help`
+                Err(e) => {
+                    let args = args.into_iter();
+                    return match cli::parse(args, env_ignore_case, &settings::Settings::default()) {
+                        Err(ArgsError::Help) => Err(ArgsError::Help),
+                        Err(ArgsError::Version) => Err(ArgsError::Version),
+                        _ => Err(e),
+                    };
+                }
+            }
+        };
+        cli::parse(args.into_iter(), env_ignore_case, &settings)
+    }
+}
+
+// The filename that stands for standard input
+const STDIN: &str = "-";
+
 // 'dyn Error' here stands for 'dynamic error' and allows the function to return any return value
 // that is an Error type
 // Box<dyn Error> is a trait object which allows us to return a type that implements the
 // Error trait which is good for returning different error values that may be of
 // different types in different error cases
-pub fn run(config: Config) -> Result<(), Box<dyn Error>> {
-    let contents = fs::read_to_string(config.filename)?;
+// On success we report whether anything was found - a selected line, or with -L a file listed -
+// which main turns into grep's exit status
+pub fn run(mut config: Config) -> Result<bool, Box<dyn Error>> {
+    config.output.color = config.color.use_color(io::stdout().is_terminal()) && !config.output.json;
+
+    if config.watch {
+        return watch::watch(&config).map_err(Into::into);
+    }
+    let (files, with_filename, walk_failed) = inputs(&config);
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+
+    let mut totals = Totals { failed: walk_failed, ..Totals::default() };
+    if config.jobs <= 1 || files.len() <= 1 {
+        // Searching straight into stdout keeps memory use flat however big the input is
+        for file in &files {
+            let outcome = search_file(&config, file, with_filename, totals.printed_any, &mut out);
+            if !totals.finish(&file.path, outcome) {
+                break;
+            }
+        }
+    } else {
+        // Each worker searches a file into a buffer that's passed on when it's that file's turn.
+        // Workers can't know whether an earlier file printed anything, so the separator between
+        // files' context groups is added here instead, before a file's first output
+        let search = |file: &Input, out: &mut parallel::Output<FileOutcome>| {
+            search_file(&config, file, with_filename, false, out)
+        };
+        let mut index = 0;
+        let mut started = false;
+        // A failure to write a file's output is reported along with how its search went
+        let mut failed = None;
+        parallel::ordered_map(&files, config.jobs, search, |piece| match piece {
+            parallel::Piece::Output(output) => {
+                if failed.is_none() {
+                    let separate = !started && totals.printed_any && config.separates_files();
+                    let written = if separate && config.mode == Mode::Lines {
+                        output::write_separator(&mut out, &config.output)
+                            .and_then(|()| out.write_all(&output))
+                    } else {
+                        out.write_all(&output)
+                    };
+                    failed = written.err();
+                }
+                started = true;
+                true
+            }
+            parallel::Piece::Done(outcome) => {
+                let file = &files[index].path;
+                index += 1;
+                started = false;
+                match failed.take() {
+                    None => totals.finish(file, outcome),
+                    Some(e) => totals.finish(file, FileOutcome { error: Some(e), ..outcome }),
+                }
+            }
+        });
+    }
+    let mut result = Ok(());
+    if config.output.json && !totals.stopped {
+        result = json::write_summary(
+            &mut out,
+            totals.searches,
+            totals.searches_with_match,
+            totals.matched_lines,
+        );
+    }
+    // A reader that has gone away isn't worth complaining about
+    match result.and_then(|()| out.flush()) {
+        Err(ref e) if e.kind() == io::ErrorKind::BrokenPipe => {}
+        result => result?,
+    }
+
+    if totals.failed {
+        return Err("some files could not be searched".into());
+    }
+
+    Ok(totals.found)
+}
+
+// The files to search, and whether their names should be printed.  Like grep, every hit is
+// prefixed with its file name as soon as more than one file could be searched - that is when
+// several operands were given or any of them is a directory.  Also whether some part of a
+// directory couldn't be walked, which is reported here and makes for grep's exit status of 2
+fn inputs(config: &Config) -> (Vec<Input>, bool, bool) {
+    let mut with_filename = config.filenames.len() > 1;
+    let mut files: Vec<Input> = Vec::new();
+    let mut failed = false;
+    for name in &config.filenames {
+        let path = Path::new(name);
+        if name == STDIN {
+            files.push(Input { path: path.to_path_buf(), named: true });
+        } else if path.is_dir() {
+            with_filename = true;
+            let (mut found, errors) = walk::files(path, &config.walk);
+            for error in &errors {
+                eprintln!("minigrep: {}", error);
+            }
+            failed |= !errors.is_empty();
+            if let Some(queries) = config.index_queries() {
+                if let Some(index) = index::open(path) {
+                    found.retain(|file| index.may_contain(path, file, &queries));
+                }
+            }
+            files.extend(found.into_iter().map(|path| Input { path, named: false }));
+        } else if config.walk.globs.allows(path) {
+            files.push(Input { path: path.to_path_buf(), named: true });
+        }
+    }
+    match config.sort {
+        SortBy::None => {}
+        SortBy::Path => files.sort_by(|a, b| a.path.cmp(&b.path)),
+        // Files we can't get the time of come first, and fail to open soon after
+        SortBy::Modified => files.sort_by_cached_key(|file| {
+            fs::metadata(&file.path).and_then(|metadata| metadata.modified()).ok()
+        }),
+    }
+
+    (files, with_filename, failed)
+}
+
+// What we've learned from the files searched so far
+#[derive(Default)]
+struct Totals {
+    found: bool,
+    printed_any: bool,
+    failed: bool,
+    // Set when our reader went away and we gave up
+    stopped: bool,
+    // Files searched without an error, files with at least one selected line, and selected lines
+    searches: usize,
+    searches_with_match: usize,
+    matched_lines: usize,
+}
+
+impl Totals {
+    // Record how a file went, reporting any error.  Returns false once we should stop searching
+    // altogether
+    fn finish(&mut self, file: &Path, outcome: FileOutcome) -> bool {
+        self.found |= outcome.found;
+        self.printed_any |= outcome.printed;
+        self.matched_lines += outcome.count;
+        if outcome.count > 0 {
+            self.searches_with_match += 1;
+        }
+        match outcome.error {
+            None => {
+                self.searches += 1;
+                true
+            }
+            // Stop quietly when whoever is reading our output goes away (eg: `| head`)
+            Some(ref e) if e.kind() == io::ErrorKind::BrokenPipe => {
+                self.stopped = true;
+                false
+            }
+            Some(e) => {
+                eprintln!("minigrep: {}: {}", display_name(file).display(), e);
+                self.failed = true;
+                true
+            }
+        }
+    }
+}
+
+// A file to search
+struct Input {
+    path: PathBuf,
+    // Whether the file was named on the command line rather than found in a directory
+    named: bool,
+}
+
+// How the search of a single file went
+struct FileOutcome {
+    // Whether the file counts towards a successful exit status
+    found: bool,
+    // Whether anything was written for the file
+    printed: bool,
+    // The number of selected lines
+    count: usize,
+    error: Option<io::Error>,
+}
 
-    let results = if config.case_sensitive {
-        search(&config.query, &contents)
+impl Config {
+    // Whether context groups from different files need a `--` between them
+    fn separates_files(&self) -> bool {
+        self.context.is_enabled() && !self.output.json
+    }
+
+    // The trigrams to look up in a directory's index, if it can narrow down this search.  Files
+    // ruled out are never opened, so that only works for the modes that say nothing about files
+    // without a match, and only when the index has seen what we'll search: not the insides of
+    // compressed files, nor fields that may have been unescaped before they were matched
+    fn index_queries(&self) -> Option<Vec<Vec<u32>>> {
+        let quiet_without_match = matches!(self.mode, Mode::Lines | Mode::FilesWithMatches);
+        if !self.use_index || !quiet_without_match || self.decompress || self.field.is_some() {
+            return None;
+        }
+        if !self.matcher.selects_substrings() {
+            return None;
+        }
+        index::query_trigrams(&self.patterns)
+    }
+}
+
+// The name a file is shown with; standard input doesn't have one of its own
+fn display_name(file: &Path) -> &Path {
+    if file == Path::new(STDIN) {
+        Path::new("(standard input)")
+    } else {
+        file
+    }
+}
+
+// Search one file and write whatever the current mode prints for it to `out`
+// `separate` asks for a `--` before the first line, to split context groups across files
+fn search_file(
+    config: &Config,
+    file: &Input,
+    with_filename: bool,
+    separate: bool,
+    out: &mut impl Write,
+) -> FileOutcome {
+    let failed = |e| FileOutcome { found: false, printed: false, count: 0, error: Some(e) };
+    let label = display_name(&file.path);
+    // With `--code-only` or `--comments-only`, a file we can't tell code from comments in is left
+    // out rather than searched as if it were all one or the other
+    let language = lang::Language::from_path(&file.path);
+    if config.only.is_some() && language.is_none() {
+        return FileOutcome { found: false, printed: false, count: 0, error: None };
+    }
+    let reader: Box<dyn BufRead> = if file.path == Path::new(STDIN) {
+        Box::new(BufReader::new(io::stdin()))
     } else {
-        search_case_insensitive(&config.query, &contents)
+        match File::open(&file.path) {
+            Ok(f) => Box::new(BufReader::new(f)),
+            Err(e) => return failed(e),
+        }
+    };
+    let (mut reader, compressed) = match decompressed(reader, config.decompress) {
+        Ok(opened) => opened,
+        Err(e) => return failed(e),
+    };
+    let binary = match looks_binary(&mut reader) {
+        Ok(binary) => config.binary != BinaryMode::Text && binary,
+        Err(e) => return failed(e),
+    };
+    // Source trees are full of images and build artifacts; nobody asked about those
+    if binary && !file.named && config.binary == BinaryMode::Auto {
+        return FileOutcome { found: false, printed: false, count: 0, error: None };
+    }
+    if let (true, Some(template)) = (config.in_place, &config.replace) {
+        return rewrite_file(config, file, template, binary || compressed, reader, out);
+    }
+
+    let path = if with_filename { Some(label) } else { None };
+    // Only the default mode prints lines as it goes, so context is pointless in the others
+    let context = if config.mode == Mode::Lines { config.context } else { ContextOptions::default() };
+    let mut count = 0;
+    let mut printed = false;
+    // `--fuzzy` ranks a file's lines by how close they are to the query, so they can only be
+    // printed once the whole file has been searched
+    let ranked = config.matcher.is_fuzzy() && config.mode == Mode::Lines && !binary;
+    let mut ranking = Vec::new();
+    // `--unique` lines in the order they were first selected, and where each one is by its text
+    let mut unique: Vec<UniqueLine> = Vec::new();
+    let mut unique_index: HashMap<String, usize> = HashMap::new();
+    // `found` is where the line matched, when that's already known
+    let mut sink = |event: Event<Match, ContextLine>, found: Option<Vec<Range<usize>>>| {
+        if let Event::Match(_) = event {
+            count += 1;
+        }
+        // A binary file's lines are only counted, see below
+        if config.mode != Mode::Lines || binary {
+            return Ok(());
+        }
+        if ranked {
+            if let Event::Match(m) = event {
+                let edits = config.matcher.distance(m.line).unwrap_or(0);
+                let line = OwnedLine {
+                    line_number: m.line_number,
+                    byte_offset: m.byte_offset,
+                    line: m.line.to_string(),
+                };
+                ranking.push((edits, m.column, line));
+            }
+            return Ok(());
+        }
+        if config.unique {
+            if let Event::Match(m) = event {
+                match unique_index.get(m.line) {
+                    Some(&index) => unique[index].count += 1,
+                    None => {
+                        unique_index.insert(m.line.to_string(), unique.len());
+                        unique.push(UniqueLine {
+                            line: OwnedLine {
+                                line_number: m.line_number,
+                                byte_offset: m.byte_offset,
+                                line: m.line.to_string(),
+                            },
+                            column: m.column,
+                            found: found.unwrap_or_else(|| highlights(config, m.line)),
+                            count: 1,
+                        });
+                    }
+                }
+            }
+            return Ok(());
+        }
+
+        if !printed {
+            start_output(config, label, separate, out)?;
+            printed = true;
+        }
+        match event {
+            Event::Match(m) => {
+                let found = found.unwrap_or_else(|| highlights(config, m.line));
+                write_selected(config, label, path, &m, &found, None, out)
+            }
+            // Records say where they came from so there's nothing to separate
+            _ if config.output.json => match event {
+                Event::Context(line) => json::write_context(out, label, &line),
+                _ => Ok(()),
+            },
+            Event::Context(line) => output::write_context(out, &config.output, path, &line),
+            Event::Separator => output::write_separator(out, &config.output),
+        }
+    };
+    let result = if config.multiline {
+        // The whole file has to be in memory to match across lines
+        let mut contents = Vec::new();
+        reader.read_to_end(&mut contents).and_then(|_| {
+            let contents = String::from_utf8_lossy(&contents);
+            let events =
+                config.matcher.search_multiline_limited(&contents, context, config.max_count);
+            for event in events {
+                match event {
+                    Event::Match((m, found)) => sink(Event::Match(m), Some(found))?,
+                    Event::Context(line) => sink(Event::Context(line), None)?,
+                    Event::Separator => sink(Event::Separator, None)?,
+                }
+            }
+            Ok(())
+        })
+    } else if let (Some(only), Some(language)) = (config.only, language) {
+        // Every line goes through the tokenizer, so it knows what the next one starts in.  The
+        // matches that are in the right place are kept for the sink to highlight
+        let mut tokenizer = lang::Tokenizer::new(language);
+        let kept = RefCell::new(Vec::new());
+        let select = |line: &str| {
+            let regions = tokenizer.classify(line);
+            let mut found = config.matcher.find_all(line);
+            found.retain(|range| regions.at(range.start) == only);
+            let first = found.first().cloned();
+            *kept.borrow_mut() = found;
+            first
+        };
+        config.matcher.search_lines(reader, context, config.max_count, select, |event| match event {
+            Event::Match(_) => sink(event, Some(kept.take())),
+            _ => sink(event, None),
+        })
+    } else if let Some(field) = &config.field {
+        let mut line_number = 0;
+        let kept = RefCell::new(Vec::new());
+        let select = |line: &str| {
+            line_number += 1;
+            let (found, highlights) = select_field(config, field, label, line_number, line)?;
+            *kept.borrow_mut() = highlights;
+            Some(found)
+        };
+        config.matcher.search_lines(reader, context, config.max_count, select, |event| match event {
+            Event::Match(_) => sink(event, Some(kept.take())),
+            _ => sink(event, None),
+        })
+    } else {
+        let select = |line: &str| config.matcher.select(line);
+        config.matcher.search_lines(reader, context, config.max_count, select, |event| {
+            sink(event, None)
+        })
+    };
+    let result = result.and_then(|()| {
+        // Closest first; lines that are just as close stay in file order
+        ranking.sort_by_key(|(edits, _, _)| *edits);
+        for (edits, column, line) in &ranking {
+            if !printed {
+                start_output(config, label, separate, out)?;
+                printed = true;
+            }
+            let m = Match {
+                line_number: line.line_number,
+                byte_offset: line.byte_offset,
+                column: *column,
+                line: &line.line,
+            };
+            write_selected(config, label, path, &m, &highlights(config, m.line), Some(*edits), out)?;
+        }
+        for unique in &unique {
+            if !printed {
+                start_output(config, label, separate, out)?;
+                printed = true;
+            }
+            let m = Match {
+                line_number: unique.line.line_number,
+                byte_offset: unique.line.byte_offset,
+                column: unique.column,
+                line: &unique.line.line,
+            };
+            output::write_unique_match(out, &config.output, path, &m, unique.count, &unique.found)?;
+        }
+        Ok(())
+    });
+    let result = result.and_then(|()| match config.mode {
+        Mode::Lines if binary && count > 0 => {
+            printed = true;
+            if config.output.json {
+                json::write_begin(out, label)?;
+                json::write_end(out, label, count)
+            } else {
+                output::write_binary_match(out, label)
+            }
+        }
+        Mode::Lines if config.output.json && printed => json::write_end(out, label, count),
+        Mode::Lines => Ok(()),
+        Mode::Count => output::write_count(out, &config.output, path, count),
+        Mode::FilesWithMatches if count > 0 => output::write_path(out, &config.output, label),
+        Mode::FilesWithoutMatch if count == 0 => output::write_path(out, &config.output, label),
+        Mode::FilesWithMatches | Mode::FilesWithoutMatch => Ok(()),
+    });
+
+    let found = match config.mode {
+        Mode::FilesWithoutMatch => count == 0 && result.is_ok(),
+        _ => count > 0,
     };
+    let printed = printed || (config.mode != Mode::Lines && found);
+    FileOutcome { found, printed, count, error: result.err() }
+}
 
-    for line in results {
-        println!("{}", line);
+// With `-z`, a reader of the decompressed contents if `reader` is compressed.  Also says whether
+// it was
+fn decompressed<'a>(
+    mut reader: Box<dyn BufRead + 'a>,
+    decompress: bool,
+) -> io::Result<(Box<dyn BufRead + 'a>, bool)> {
+    if decompress {
+        if let Some(format) = decompress::Format::detect(reader.fill_buf()?) {
+            return Ok((decompress::decoder(format, reader)?, true));
+        }
     }
+    Ok((reader, false))
+}
 
-    // Wrapping the unit type in the Ok() in this case signifies that we only care about
-    // the side effects of the run() function
-    Ok(())
+// Like grep and ripgrep we only look for a NUL byte in the first buffer full: text files
+// practically never contain one, while most binary formats have some in their header
+fn looks_binary(reader: &mut dyn BufRead) -> io::Result<bool> {
+    Ok(reader.fill_buf()?.contains(&0))
 }
 
-// The actual searching for minigrep - the heart of the program
-// There is an explicit lifetime which specifies that the returned vector should contain string
-// slices that reference slices of the argument `contents` rather than `query`
-// We're essentially telling the compiler the lifetime of the returned vector is the
-// same as the lifetime of contents!  Query can be tossed after we finish searching
-fn search<'a>(query: &str, contents: &'a str) -> Vec<&'a str> {
-    // Take advantage of iterator adapter methods like filter!1
-    contents.lines()
-        .filter(|line| line.contains(query))
-        .collect()
+// Whatever goes before the first line printed for a file: the `begin` record with `--json`, or
+// a separator if context groups from an earlier file were printed (`separate`)
+fn start_output(
+    config: &Config,
+    label: &Path,
+    separate: bool,
+    out: &mut impl Write,
+) -> io::Result<()> {
+    if config.output.json {
+        json::write_begin(out, label)
+    } else if separate && config.separates_files() {
+        output::write_separator(out, &config.output)
+    } else {
+        Ok(())
+    }
+}
 
+// The matches in a selected line that will be highlighted, or with `--json` reported.  Finding
+// them is only worth it when they are going to be used
+fn highlights(config: &Config, line: &str) -> Vec<Range<usize>> {
+    if config.output.json || (config.output.color && config.replace.is_none()) {
+        config.matcher.find_all(line)
+    } else {
+        Vec::new()
+    }
 }
 
-fn search_case_insensitive<'a>(query: &str, contents: &'a str) -> Vec<&'a str> {
-    let query = query.to_lowercase();
-    let mut results = Vec::new();
+// With `--field` or `--csv-column`, where `line` was selected and the parts of it to highlight,
+// going by the field alone.  An invalid record is reported and isn't selected
+fn select_field(
+    config: &Config,
+    field: &Field,
+    label: &Path,
+    line_number: usize,
+    line: &str,
+) -> Option<(Range<usize>, Vec<Range<usize>>)> {
+    let value = match field.extract(line) {
+        Ok(value) => value?,
+        Err(message) => {
+            eprintln!("minigrep: {}:{}: {}", label.display(), line_number, message);
+            return None;
+        }
+    };
+    let found = config.matcher.find_all(&value.text);
+    if found.is_empty() != config.matcher.invert {
+        return None;
+    }
+    if config.matcher.invert {
+        return Some((0..0, Vec::new()));
+    }
+    // Matches in a value that was unescaped can't be pointed at in the line, only the field can
+    if !value.verbatim {
+        return Some((value.at..value.at, Vec::new()));
+    }
+    let found: Vec<Range<usize>> =
+        found.into_iter().map(|range| range.start + value.at..range.end + value.at).collect();
+    Some((found[0].clone(), found))
+}
+
+// Print a selected line, with any replacement applied.  `found` is where the line matched (see
+// `highlights`) and `edits` is how far from the query the line is, with `--fuzzy`
+fn write_selected(
+    config: &Config,
+    label: &Path,
+    path: Option<&Path>,
+    m: &Match,
+    found: &[Range<usize>],
+    edits: Option<usize>,
+    out: &mut impl Write,
+) -> io::Result<()> {
+    if config.output.json {
+        return json::write_match(out, label, m, found, edits);
+    }
 
-    for line in contents.lines() {
-        if line.to_lowercase().contains(&query) {
-            results.push(line);
+    let (line, highlights) = match &config.replace {
+        // With a replacement it's the replaced text that gets highlighted
+        Some(template) => {
+            let (line, highlights) = config.matcher.replace(m.line, template);
+            (Cow::Owned(line), Cow::Owned(highlights))
         }
+        None => (Cow::Borrowed(m.line), Cow::Borrowed(found)),
+    };
+    let m = Match { line: &line, ..*m };
+    match edits {
+        Some(edits) => output::write_fuzzy_match(out, &config.output, path, &m, edits, &highlights),
+        None => output::write_match(out, &config.output, path, &m, &highlights),
+    }
+}
+
+// `--in-place`: apply the replacement to the whole of `file` and write it back, or with
+// `--dry-run` print the changes as a diff.  Nothing else is printed
+fn rewrite_file(
+    config: &Config,
+    file: &Input,
+    template: &str,
+    // Whether the file is binary or compressed, which we can't write back
+    not_text: bool,
+    mut reader: Box<dyn BufRead>,
+    out: &mut impl Write,
+) -> FileOutcome {
+    let outcome = |count: usize, error| FileOutcome {
+        found: count > 0,
+        printed: config.dry_run && count > 0,
+        count,
+        error,
+    };
+    let refuse = |why: &str| {
+        let why = format!("{}, not rewriting it", why);
+        outcome(0, Some(io::Error::new(io::ErrorKind::InvalidInput, why)))
+    };
+    if file.path == Path::new(STDIN) {
+        return refuse("can't rewrite standard input");
+    }
+    // Writing back a lossily decoded or decompressed file would corrupt it
+    if not_text {
+        return refuse("binary or compressed file");
     }
+    let mut contents = String::new();
+    if let Err(e) = reader.read_to_string(&mut contents) {
+        return if e.kind() == io::ErrorKind::InvalidData {
+            refuse("not valid UTF-8")
+        } else {
+            outcome(0, Some(e))
+        };
+    }
+
+    let rewrite = replace::rewrite(&config.matcher, template, &contents);
+    let count = rewrite.changes.len();
+    let result = if config.dry_run {
+        replace::write_diff(out, &file.path, &rewrite.changes)
+    } else if count > 0 {
+        replace::write_atomically(&file.path, rewrite.contents.as_bytes())
+    } else {
+        Ok(())
+    };
+
+    outcome(count, result.err())
+}
+
+// Test helpers that keep the original tests below in terms of plain lines.  Both hand the work to
+// Matcher::search; an escaped literal can never fail to compile so the unwrap is safe
+#[cfg(test)]
+fn search<'a>(query: &str, contents: &'a str) -> Vec<&'a str> {
+    matched_lines(Matcher::literal(query, true).unwrap().search(contents))
+}
+
+#[cfg(test)]
+fn search_case_insensitive<'a>(query: &str, contents: &'a str) -> Vec<&'a str> {
+    matched_lines(Matcher::literal(query, false).unwrap().search(contents))
+}
 
-    results
+#[cfg(test)]
+fn matched_lines<'a>(matches: Vec<Match<'a>>) -> Vec<&'a str> {
+    matches.into_iter().map(|m| m.line).collect()
 }
 
 #[cfg(test)]
@@ -121,4 +1333,256 @@ Trust me.";
             search_case_insensitive(query, contents)
             );
     }
+
+    #[test]
+    fn regex_mode() {
+        let matcher = Matcher::regex(r"^fn\s+\w+", true).unwrap();
+        let contents = "\
+fn main() {
+    let f = fn_pointer;
+}
+fn  helper() {}";
+
+        assert_eq!(
+            vec!["fn main() {", "fn  helper() {}"],
+            matched_lines(matcher.search(contents))
+            );
+    }
+
+    #[test]
+    fn regex_mode_case_insensitive() {
+        let matcher = Matcher::regex("^t.*e$", false).unwrap();
+        let contents = "\
+Rust:
+Pick three.
+Trust me.
+Tape";
+
+        assert_eq!(vec!["Tape"], matched_lines(matcher.search(contents)));
+    }
+
+    #[test]
+    fn literal_mode_escapes_metacharacters() {
+        let matcher = Matcher::literal("a.c", true).unwrap();
+
+        assert_eq!(vec!["a.c"], matched_lines(matcher.search("abc\na.c")));
+    }
+
+    #[test]
+    fn match_positions() {
+        let matcher = Matcher::literal("tape", false).unwrap();
+        let contents = "Rust:\r\nsafe, fast, productive.\nDuct tape.\n";
+
+        assert_eq!(
+            vec![Match { line_number: 3, byte_offset: 31, column: 6, line: "Duct tape." }],
+            matcher.search(contents)
+            );
+    }
+
+    #[test]
+    fn context_around_matches() {
+        let matcher = Matcher::literal("x", true).unwrap();
+        let contents = "a\nx\nb\nc\nd\nx";
+        let events = matcher.search_with_context(contents, ContextOptions { before: 1, after: 1 });
+
+        assert_eq!(
+            vec![
+                Event::Context(ContextLine { line_number: 1, byte_offset: 0, line: "a" }),
+                Event::Match(Match { line_number: 2, byte_offset: 2, column: 1, line: "x" }),
+                Event::Context(ContextLine { line_number: 3, byte_offset: 4, line: "b" }),
+                Event::Separator,
+                Event::Context(ContextLine { line_number: 5, byte_offset: 8, line: "d" }),
+                Event::Match(Match { line_number: 6, byte_offset: 10, column: 1, line: "x" }),
+            ],
+            events
+            );
+    }
+
+    #[test]
+    fn multiline_matches() {
+        let options = MatcherOptions { regex: true, multiline: true, ..MatcherOptions::default() };
+        let matcher = Matcher::new(r"fn \w+\(\s*self,\s*x", &options).unwrap();
+        let contents = "fn a(self) {}\nfn b(\r\n    self,\n    x: u8,\n) {}\nfn c() {}\n";
+        let events = matcher.search_multiline(contents, ContextOptions { before: 0, after: 1 });
+        let selected = |line_number, byte_offset, line, part: Range<usize>| {
+            Event::Match((Match { line_number, byte_offset, column: part.start + 1, line }, vec![part]))
+        };
+
+        assert_eq!(
+            vec![
+                selected(2, 14, "fn b(", 0..5),
+                selected(3, 21, "    self,", 0..9),
+                selected(4, 31, "    x: u8,", 0..5),
+                Event::Context(ContextLine { line_number: 5, byte_offset: 42, line: ") {}" }),
+            ],
+            events
+            );
+    }
+
+    #[test]
+    fn multiline_anchors() {
+        let options = MatcherOptions { regex: true, multiline: true, ..MatcherOptions::default() };
+        let matcher = Matcher::new(r"\{$\n^\}", &options).unwrap();
+        let events = matcher.search_multiline("{\n}\n{x\n}\n", ContextOptions::default());
+        let lines: Vec<usize> = events.iter()
+            .filter_map(|event| match event {
+                Event::Match((m, _)) => Some(m.line_number),
+                _ => None,
+            })
+            .collect();
+
+        assert_eq!(vec![1, 2], lines);
+    }
+
+    #[test]
+    fn multiline_max_count() {
+        let options = MatcherOptions { regex: true, multiline: true, ..MatcherOptions::default() };
+        let matcher = Matcher::new(r"a\nb", &options).unwrap();
+        let context = ContextOptions { before: 0, after: 1 };
+        let events = matcher.search_multiline_limited("a\nb\nx\na\nb\n", context, Some(1));
+        let lines: Vec<(usize, bool)> = events.iter()
+            .map(|event| match event {
+                Event::Match((m, _)) => (m.line_number, true),
+                Event::Context(line) => (line.line_number, false),
+                Event::Separator => (0, false),
+            })
+            .collect();
+
+        // The match on lines 1 and 2 is the one that counts, both its lines are printed
+        assert_eq!(vec![(1, true), (2, true), (3, false)], lines);
+    }
+
+    #[test]
+    fn inverted_match() {
+        let options = MatcherOptions { invert: true, ..MatcherOptions::default() };
+        let matcher = Matcher::new("duct", &options).unwrap();
+        let contents = "\
+Rust:
+safe, fast, productive.
+Pick three.";
+
+        assert_eq!(vec!["Rust:", "Pick three."], matched_lines(matcher.search(contents)));
+    }
+
+    #[test]
+    fn whole_word() {
+        let options = MatcherOptions { whole_word: true, ..MatcherOptions::default() };
+        let matcher = Matcher::new("rust", &options).unwrap();
+        let contents = "\
+trust me
+rust: fast
+rusty nail
+(rust)";
+
+        assert_eq!(vec!["rust: fast", "(rust)"], matched_lines(matcher.search(contents)));
+    }
+
+    #[test]
+    fn unicode_case_folding() {
+        let matcher = Matcher::literal("straße", false).unwrap();
+        let contents = "\
+DIE STRASSE
+İstanbul straße
+strasbourg";
+
+        let found = matcher.search(contents);
+        // Columns and highlights point into the line as it is, not into its folded copy
+        assert_eq!(5, found[0].column);
+        assert_eq!(11, found[1].column);
+        assert_eq!(vec!["DIE STRASSE", "İstanbul straße"], matched_lines(found));
+        assert_eq!(vec![10..17], matcher.find_all("İstanbul straße"));
+    }
+
+    #[test]
+    fn whole_word_unicode() {
+        let options = MatcherOptions {
+            whole_word: true,
+            ignore_case: true,
+            ..MatcherOptions::default()
+        };
+        let matcher = Matcher::new("can", &options).unwrap();
+        assert!(!matcher.is_match("I can't"));
+        assert_eq!(vec![0..3, 8..11, 18..21], matcher.find_all("CAN you can, scan can"));
+
+        let options = MatcherOptions { whole_word: true, regex: true, ..MatcherOptions::default() };
+        let matcher = Matcher::new("rust|rusty", &options).unwrap();
+        assert_eq!(Some(0..5), matcher.find("rusty nail"));
+        // `\b` finds no word in `$x`, but it is one like it is without --regex
+        let matcher = Matcher::new(r"\$x", &options).unwrap();
+        assert_eq!(Some(6..8), matcher.find("cost: $x each"));
+        assert_eq!("cost: $y each", matcher.replace("cost: $x each", "$$y").0);
+        assert!(!matcher.is_match("cost: $xy each"));
+        // Even when the wrapped regex finds a word later on
+        let matcher = Matcher::new(r"\$x|y", &options).unwrap();
+        assert_eq!(vec![0..2, 3..4], matcher.find_all("$x y"));
+    }
+
+    #[test]
+    fn streaming_search() {
+        let matcher = Matcher::literal("x", true).unwrap();
+        // A tiny buffer makes sure lines longer than the buffer are stitched back together
+        let reader = BufReader::with_capacity(2, "ab\r\nxyz\n\nc\nx".as_bytes());
+        let mut seen = Vec::new();
+        matcher.search_reader(reader, ContextOptions { before: 1, after: 0 }, |event| {
+            seen.push(match event {
+                Event::Match(m) => format!("{}:{}:{}:{}", m.line_number, m.byte_offset, m.column, m.line),
+                Event::Context(c) => format!("{}-{}-{}", c.line_number, c.byte_offset, c.line),
+                Event::Separator => "--".to_string(),
+            });
+            Ok(())
+        }).unwrap();
+
+        assert_eq!(vec!["1-0-ab", "2:4:1:xyz", "--", "4-9-c", "5:11:1:x"], seen);
+    }
+
+    #[test]
+    fn streaming_decodes_invalid_utf8() {
+        let matcher = Matcher::literal("x", true).unwrap();
+        let mut seen = Vec::new();
+        let reader = &b"caf\xe9 x\n\xff\nx\n"[..];
+        matcher.search_reader(reader, ContextOptions::default(), |event| {
+            if let Event::Match(m) = event {
+                seen.push((m.line_number, m.byte_offset, m.line.to_string()));
+            }
+            Ok(())
+        }).unwrap();
+
+        // Offsets still count the bytes in the file
+        assert_eq!(vec![(1, 0, "caf\u{fffd} x".to_string()), (3, 9, "x".to_string())], seen);
+    }
+
+    #[test]
+    fn replace_with_captures() {
+        let matcher = Matcher::regex(r"(?P<key>\w+)=(\d+)", true).unwrap();
+        let (line, ranges) = matcher.replace("a=1, b=22", "$2:${key}$$");
+        assert_eq!("1:a$, 22:b$", line);
+        assert_eq!(vec![0..4, 6..11], ranges);
+
+        // A plain query's template is taken literally, and folding still finds `STRASSE`
+        let matcher = Matcher::literal("straße", false).unwrap();
+        assert_eq!("the $1 road", matcher.replace("the STRASSE road", "$1").0);
+    }
+
+    #[test]
+    fn several_patterns() {
+        let options = MatcherOptions::default();
+        let matcher = Matcher::with_patterns(&["fn", "fn main", "let"], &options).unwrap();
+        // The longest of the queries matching at the same place wins
+        assert_eq!(vec![0..7, 10..13], matcher.find_all("fn main { let x = f(); }"));
+
+        let options = MatcherOptions { ignore_case: true, whole_word: true, ..options };
+        let matcher = Matcher::with_patterns(&["straße", "weg"], &options).unwrap();
+        assert_eq!(vec![0..7, 8..11], matcher.find_all("STRASSE Weg wegen"));
+
+        let options = MatcherOptions { regex: true, ..MatcherOptions::default() };
+        let matcher = Matcher::with_patterns(&[r"a|b", r"(\d)+"], &options).unwrap();
+        assert_eq!(vec![0..1, 2..4], matcher.find_all("b 42"));
+        // Group 1 belongs to the second pattern, so it's empty where the first one matched
+        assert_eq!(" 2", matcher.replace("b 42", "$1").0);
+    }
+
+    #[test]
+    fn invalid_regex() {
+        assert!(Matcher::regex("(unclosed", true).is_err());
+    }
 }
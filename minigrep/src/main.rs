@@ -1,30 +1,76 @@
 // Bring in the standard library module to accesss `args`
 // std::env::args_os could be used to recognize non-ASCII values
 use std::env;
+use std::path::Path;
 // Bring in the standard library module to handle exiting process
 use std::process;
 
-use minigrep;
-use minigrep::Config;
+use minigrep::{ArgsError, Config};
 
 // The majority of the functionality in a Rust program should belong in
 // lib.rs while the usage of functions defined in lib.rs go in main.rs
 
+// Exit statuses follow grep: something was found, nothing was found, or something went wrong
+const FOUND: i32 = 0;
+const NOT_FOUND: i32 = 1;
+const ERROR: i32 = 2;
+
 fn main() {
+    // `minigrep index build [DIR]` maintains an index rather than searching.  `minigrep -- index
+    // build` still searches for `index` in `build`
+    let args: Vec<String> = env::args().collect();
+    if args.len() >= 3 && args[1] == "index" && args[2] == "build" {
+        process::exit(build_index(&args[3..]));
+    }
+
     // Result<T, E>.upwrap_or_else() allows a user to define non-panic! error handling
     // if the inner value Result<T, E> is Ok() then it behaves like a normal Ok()
     // otherwise the code in the closure is run
     // we pass env::args() directly into Config::new as env::args() is
     // an iterator which will allow Config::new to have ownership
-    let config = Config::new(env::args()).unwrap_or_else(|err| {
-        eprintln!("Problem parsing arguments: {}", err);
-        process::exit(1);
+    let config = Config::new(args.into_iter()).unwrap_or_else(|err| match err {
+        // Asking for help or the version isn't a mistake, so it goes to stdout and succeeds
+        ArgsError::Help | ArgsError::Version => {
+            println!("{}", err);
+            process::exit(0);
+        }
+        _ => {
+            eprintln!("Problem parsing arguments: {}", err);
+            eprintln!("Try 'minigrep --help' for more information.");
+            process::exit(ERROR);
+        }
     });
 
-    // We don't benefit from using unwrap_or_else here as the Ok() result is always the
-    // unit type so we only care about detecting errors
-    if let Err(e) = minigrep::run(config) {
-        eprintln!("Application error: {}", e);
-        process::exit(1);
+    match minigrep::run(config) {
+        Ok(true) => process::exit(FOUND),
+        Ok(false) => process::exit(NOT_FOUND),
+        Err(e) => {
+            eprintln!("Application error: {}", e);
+            process::exit(ERROR);
+        }
+    }
+}
+
+fn build_index(args: &[String]) -> i32 {
+    let dir = match args {
+        [] => ".",
+        [dir] => dir.as_str(),
+        _ => {
+            eprintln!("Problem parsing arguments: 'index build' takes at most one directory");
+            return ERROR;
+        }
+    };
+    match minigrep::build_index(Path::new(dir)) {
+        Ok(stats) => {
+            println!(
+                "indexed {} files in {} ({} updated, {} removed)",
+                stats.files, dir, stats.updated, stats.removed
+            );
+            FOUND
+        }
+        Err(e) => {
+            eprintln!("Application error: {}", e);
+            ERROR
+        }
     }
 }
@@ -0,0 +1,333 @@
+// Formatting search results for the terminal
+use std::fmt::Display;
+use std::io::{self, Write};
+use std::ops::Range;
+use std::path::Path;
+
+use crate::{ContextLine, Match};
+
+// Which pieces of position information to print in front of each matching line
+#[derive(Default)]
+pub struct OutputOptions {
+    // `-n`: the 1-based line number
+    pub line_number: bool,
+    // `-b`: the byte offset of the start of the line within the file
+    pub byte_offset: bool,
+    // `--column`: the 1-based byte column of the first match in the line
+    pub column: bool,
+    // Whether to use ANSI colors; `run` works this out from the `--color` choice
+    pub color: bool,
+    // `--json`: print JSON Lines records instead of text (see json.rs)
+    pub json: bool,
+    // `--colors`: what the colors are when they're on
+    pub colors: Palette,
+}
+
+// `--color=WHEN`
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ColorChoice {
+    // Only color when writing to a terminal (the default)
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    pub fn parse(when: &str) -> Option<ColorChoice> {
+        match when {
+            "auto" => Some(ColorChoice::Auto),
+            "always" => Some(ColorChoice::Always),
+            "never" => Some(ColorChoice::Never),
+            _ => None,
+        }
+    }
+
+    pub fn use_color(self, is_terminal: bool) -> bool {
+        match self {
+            ColorChoice::Auto => is_terminal,
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+        }
+    }
+}
+
+// The SGR parameters (eg: `1;31` for bold red) to print each part of a line with
+#[derive(Clone, Debug, PartialEq)]
+pub struct Palette {
+    pub path: String,
+    pub number: String,
+    pub separator: String,
+    pub matched: String,
+}
+
+// The same colors GNU grep uses by default
+impl Default for Palette {
+    fn default() -> Palette {
+        Palette {
+            path: "35".to_string(),
+            number: "32".to_string(),
+            separator: "36".to_string(),
+            matched: "1;31".to_string(),
+        }
+    }
+}
+
+impl Palette {
+    // Apply one `--colors` setting, `PART=STYLE` with PART one of `path`, `number`, `separator`
+    // or `match`.  Returns false if it isn't one
+    pub fn set(&mut self, setting: &str) -> bool {
+        let (part, style) = match setting.split_once('=') {
+            Some(split) => split,
+            None => return false,
+        };
+        if style.is_empty() || !style.chars().all(|c| c.is_ascii_digit() || c == ';') {
+            return false;
+        }
+        let slot = match part {
+            "path" => &mut self.path,
+            "number" => &mut self.number,
+            "separator" => &mut self.separator,
+            "match" => &mut self.matched,
+            _ => return false,
+        };
+        *slot = style.to_string();
+        true
+    }
+}
+
+// Write `text` wrapped in the escape codes for `color` if colors are on
+fn paint(
+    out: &mut impl Write,
+    options: &OutputOptions,
+    color: &str,
+    text: impl Display,
+) -> io::Result<()> {
+    if options.color {
+        write!(out, "\x1b[{}m{}\x1b[0m", color, text)
+    } else {
+        write!(out, "{}", text)
+    }
+}
+
+// Write a single match as `path:line:column:offset:text`, leaving out whichever prefixes were
+// not asked for.  The order mirrors what editors expect for jumping to `file:line:col`
+// `highlights` are the byte ranges within the line to paint as matches when colors are on
+pub fn write_match(
+    out: &mut impl Write,
+    options: &OutputOptions,
+    path: Option<&Path>,
+    m: &Match,
+    highlights: &[Range<usize>],
+) -> io::Result<()> {
+    write_prefix(out, options, path, ':', m.line_number, Some(m.column), m.byte_offset)?;
+    write_highlighted(out, options, m.line, highlights)
+}
+
+// `--fuzzy` matches also say how many edits away from the query they are, as `~N` just before
+// the text
+pub fn write_fuzzy_match(
+    out: &mut impl Write,
+    options: &OutputOptions,
+    path: Option<&Path>,
+    m: &Match,
+    edits: usize,
+    highlights: &[Range<usize>],
+) -> io::Result<()> {
+    write_prefix(out, options, path, ':', m.line_number, Some(m.column), m.byte_offset)?;
+    paint(out, options, &options.colors.number, format_args!("~{}", edits))?;
+    paint(out, options, &options.colors.separator, ':')?;
+    write_highlighted(out, options, m.line, highlights)
+}
+
+// `--unique` lines say how many times they were selected, as `Nx` just before the text
+pub fn write_unique_match(
+    out: &mut impl Write,
+    options: &OutputOptions,
+    path: Option<&Path>,
+    m: &Match,
+    count: usize,
+    highlights: &[Range<usize>],
+) -> io::Result<()> {
+    write_prefix(out, options, path, ':', m.line_number, Some(m.column), m.byte_offset)?;
+    paint(out, options, &options.colors.number, format_args!("{}x", count))?;
+    paint(out, options, &options.colors.separator, ':')?;
+    write_highlighted(out, options, m.line, highlights)
+}
+
+fn write_highlighted(
+    out: &mut impl Write,
+    options: &OutputOptions,
+    line: &str,
+    highlights: &[Range<usize>],
+) -> io::Result<()> {
+    let mut written = 0;
+    if options.color {
+        // Like grep, an empty match (eg: of `^`) has nothing to color
+        for range in highlights.iter().filter(|range| !range.is_empty()) {
+            write!(out, "{}", &line[written..range.start])?;
+            paint(out, options, &options.colors.matched, &line[range.clone()])?;
+            written = range.end;
+        }
+    }
+    writeln!(out, "{}", &line[written..])
+}
+
+// Context lines use `-` instead of `:` after each prefix so they can be told apart from matches.
+// They have no column since nothing matched on them
+pub fn write_context(
+    out: &mut impl Write,
+    options: &OutputOptions,
+    path: Option<&Path>,
+    line: &ContextLine,
+) -> io::Result<()> {
+    write_prefix(out, options, path, '-', line.line_number, None, line.byte_offset)?;
+    writeln!(out, "{}", line.line)
+}
+
+// `-c` prints one count per file
+pub fn write_count(
+    out: &mut impl Write,
+    options: &OutputOptions,
+    path: Option<&Path>,
+    count: usize,
+) -> io::Result<()> {
+    if let Some(path) = path {
+        paint(out, options, &options.colors.path, path.display())?;
+        paint(out, options, &options.colors.separator, ':')?;
+    }
+    writeln!(out, "{}", count)
+}
+
+// `-l` and `-L` print just the file name
+pub fn write_path(out: &mut impl Write, options: &OutputOptions, path: &Path) -> io::Result<()> {
+    paint(out, options, &options.colors.path, path.display())?;
+    writeln!(out)
+}
+
+// What's printed instead of the lines of a binary file that matched
+pub fn write_binary_match(out: &mut impl Write, path: &Path) -> io::Result<()> {
+    writeln!(out, "Binary file {} matches", path.display())
+}
+
+pub fn write_separator(out: &mut impl Write, options: &OutputOptions) -> io::Result<()> {
+    paint(out, options, &options.colors.separator, "--")?;
+    writeln!(out)
+}
+
+fn write_prefix(
+    out: &mut impl Write,
+    options: &OutputOptions,
+    path: Option<&Path>,
+    sep: char,
+    line_number: usize,
+    column: Option<usize>,
+    byte_offset: usize,
+) -> io::Result<()> {
+    if let Some(path) = path {
+        paint(out, options, &options.colors.path, path.display())?;
+        paint(out, options, &options.colors.separator, sep)?;
+    }
+    if options.line_number {
+        paint(out, options, &options.colors.number, line_number)?;
+        paint(out, options, &options.colors.separator, sep)?;
+    }
+    if options.column {
+        if let Some(column) = column {
+            paint(out, options, &options.colors.number, column)?;
+        }
+        paint(out, options, &options.colors.separator, sep)?;
+    }
+    if options.byte_offset {
+        paint(out, options, &options.colors.number, byte_offset)?;
+        paint(out, options, &options.colors.separator, sep)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(options: &OutputOptions, path: Option<&Path>) -> String {
+        let m = Match { line_number: 3, byte_offset: 42, column: 7, line: "let x = 1;" };
+        let mut out = Vec::new();
+        write_match(&mut out, options, path, &m, &[Range { start: 4, end: 5 }]).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn plain_line() {
+        assert_eq!("let x = 1;\n", render(&OutputOptions::default(), None));
+    }
+
+    #[test]
+    fn all_prefixes() {
+        let options = OutputOptions { line_number: true, byte_offset: true, column: true, ..OutputOptions::default() };
+
+        assert_eq!("src/a.rs:3:7:42:let x = 1;\n", render(&options, Some(Path::new("src/a.rs"))));
+    }
+
+    #[test]
+    fn fuzzy_match() {
+        let options = OutputOptions { line_number: true, ..OutputOptions::default() };
+        let m = Match { line_number: 3, byte_offset: 42, column: 5, line: "let nobdy;" };
+        let mut out = Vec::new();
+        write_fuzzy_match(&mut out, &options, None, &m, 1, &[]).unwrap();
+
+        assert_eq!("3:~1:let nobdy;\n", String::from_utf8(out).unwrap());
+    }
+
+    #[test]
+    fn context_line() {
+        let options = OutputOptions { line_number: true, ..OutputOptions::default() };
+        let line = ContextLine { line_number: 4, byte_offset: 53, line: "}" };
+        let mut out = Vec::new();
+        write_context(&mut out, &options, Some(Path::new("a.rs")), &line).unwrap();
+
+        assert_eq!("a.rs-4-}\n", String::from_utf8(out).unwrap());
+    }
+
+    #[test]
+    fn colored_match() {
+        let options = OutputOptions { line_number: true, color: true, ..OutputOptions::default() };
+
+        assert_eq!(
+            "\x1b[35ma.rs\x1b[0m\x1b[36m:\x1b[0m\x1b[32m3\x1b[0m\x1b[36m:\x1b[0mlet \x1b[1;31mx\x1b[0m = 1;\n",
+            render(&options, Some(Path::new("a.rs")))
+            );
+    }
+
+    #[test]
+    fn empty_matches_are_not_colored() {
+        let options = OutputOptions { color: true, ..OutputOptions::default() };
+        let m = Match { line_number: 1, byte_offset: 0, column: 1, line: "ab" };
+        let mut out = Vec::new();
+        write_match(&mut out, &options, None, &m, &[0..0, 1..1, 1..2, 2..2]).unwrap();
+
+        assert_eq!("a\x1b[1;31mb\x1b[0m\n", String::from_utf8(out).unwrap());
+    }
+
+    #[test]
+    fn custom_colors() {
+        let mut options = OutputOptions { color: true, ..OutputOptions::default() };
+        assert!(options.colors.set("match=1;32"));
+        assert!(options.colors.set("path=4"));
+        assert!(!options.colors.set("match=red"));
+        assert!(!options.colors.set("background=40"));
+
+        assert_eq!(
+            "\x1b[4ma.rs\x1b[0m\x1b[36m:\x1b[0mlet \x1b[1;32mx\x1b[0m = 1;\n",
+            render(&options, Some(Path::new("a.rs")))
+            );
+    }
+
+    #[test]
+    fn color_choice() {
+        assert_eq!(Some(ColorChoice::Never), ColorChoice::parse("never"));
+        assert_eq!(None, ColorChoice::parse("sometimes"));
+        assert!(ColorChoice::Auto.use_color(true));
+        assert!(!ColorChoice::Auto.use_color(false));
+        assert!(ColorChoice::Always.use_color(false));
+    }
+}
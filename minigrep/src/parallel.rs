@@ -0,0 +1,257 @@
+// Searching many files at once
+// Worker threads pull the next file to search from a shared counter, so a few big files don't
+// leave the other workers idle, and send their output back over a channel in pieces.  The output
+// of the file whose turn it is gets passed on as it arrives, so a huge file streams through just
+// like it does on a single thread.  Later files are only buffered up to a point: a worker with a
+// full buffer waits for its file's turn, and no worker starts a file too far past that one.  This
+// keeps memory use bounded and minigrep's output identical no matter how many threads are used
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::mem;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, SyncSender};
+use std::sync::{Condvar, Mutex};
+use std::thread;
+
+// How much output a worker collects before passing it on, or waiting until it may
+const CHUNK: usize = 64 * 1024;
+
+// The number of threads to use when the user didn't say
+pub fn default_jobs() -> usize {
+    thread::available_parallelism().map_or(1, |n| n.get())
+}
+
+// What comes back for an item: its output a piece at a time, then its result
+pub enum Piece<R> {
+    Output(Vec<u8>),
+    Done(R),
+}
+
+struct Progress {
+    // The item whose turn it is
+    head: usize,
+    stopped: bool,
+}
+
+// Progress shared between the workers and the thread handing out what they send
+struct Turn {
+    progress: Mutex<Progress>,
+    changed: Condvar,
+}
+
+impl Turn {
+    // Wait until `ready` says yes to the item whose turn it is.  Returns false if we were told to
+    // stop instead
+    fn wait(&self, ready: impl Fn(usize) -> bool) -> bool {
+        let progress = self.progress.lock().unwrap();
+        let progress = self.changed
+            .wait_while(progress, |progress| !progress.stopped && !ready(progress.head))
+            .unwrap();
+        !progress.stopped
+    }
+
+    fn update(&self, change: impl FnOnce(&mut Progress)) {
+        change(&mut self.progress.lock().unwrap());
+        self.changed.notify_all();
+    }
+}
+
+// Where `work` writes the output for an item
+pub struct Output<'a, R> {
+    index: usize,
+    buffer: Vec<u8>,
+    sender: &'a SyncSender<(usize, Piece<R>)>,
+    turn: &'a Turn,
+}
+
+impl<R> Output<'_, R> {
+    // Send what was written so far, once it's this item's turn
+    fn pass_on(&mut self) -> io::Result<()> {
+        // Nobody is reading the output any more once we've been told to stop
+        let gone = || io::Error::new(io::ErrorKind::BrokenPipe, "output is no longer wanted");
+        let index = self.index;
+        if !self.turn.wait(|head| head == index) {
+            return Err(gone());
+        }
+        let output = mem::take(&mut self.buffer);
+        self.sender.send((index, Piece::Output(output))).map_err(|_| gone())
+    }
+}
+
+impl<R> Write for Output<'_, R> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        if self.buffer.len() >= CHUNK {
+            self.pass_on()?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Run `work` on every item using up to `jobs` threads and pass the output it writes and then its
+// result to `emit`, one item after another in the same order as `items`.  If `emit` returns false
+// we stop handing out new items and return early
+pub fn ordered_map<T, R, W, E>(items: &[T], jobs: usize, work: W, mut emit: E)
+where
+    T: Sync,
+    R: Send,
+    W: Fn(&T, &mut Output<R>) -> R + Sync,
+    E: FnMut(Piece<R>) -> bool,
+{
+    let next = AtomicUsize::new(0);
+    let progress = Mutex::new(Progress { head: 0, stopped: false });
+    let turn = Turn { progress, changed: Condvar::new() };
+    let jobs = jobs.clamp(1, items.len().max(1));
+    // Enough for every worker to have finished an item while it searches the next one
+    let ahead = 2 * jobs;
+
+    thread::scope(|scope| {
+        let (sender, receiver) = mpsc::sync_channel(jobs);
+        for _ in 0..jobs {
+            let sender = sender.clone();
+            let (next, turn, work) = (&next, &turn, &work);
+            scope.spawn(move || loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                if index >= items.len() || !turn.wait(|head| index < head + ahead) {
+                    break;
+                }
+                let mut output = Output { index, buffer: Vec::new(), sender: &sender, turn };
+                let result = work(&items[index], &mut output);
+                let rest = output.buffer;
+                // The receiver is only gone once we've been told to stop
+                if !rest.is_empty() && sender.send((index, Piece::Output(rest))).is_err() {
+                    break;
+                }
+                if sender.send((index, Piece::Done(result))).is_err() {
+                    break;
+                }
+            });
+        }
+        // Only the workers hold senders now, so the loop below ends once they are all done
+        drop(sender);
+
+        let mut early: HashMap<usize, Vec<Piece<R>>> = HashMap::new();
+        let mut head = 0;
+        for (index, piece) in receiver {
+            early.entry(index).or_default().push(piece);
+            // Pass on what we have for the item whose turn it is, and for the next ones as long
+            // as the one before is done
+            while let Some(pieces) = early.remove(&head) {
+                let mut done = false;
+                for piece in pieces {
+                    done = matches!(piece, Piece::Done(_));
+                    if !emit(piece) {
+                        turn.update(|progress| progress.stopped = true);
+                        return;
+                    }
+                }
+                if !done {
+                    break;
+                }
+                head += 1;
+                turn.update(|progress| progress.head = head);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn results_come_back_in_order() {
+        let items: Vec<u64> = (0..50).collect();
+        let mut seen = Vec::new();
+        ordered_map(&items, 8, |&n, _| {
+            // Make early items finish last
+            thread::sleep(Duration::from_millis((50 - n) % 7));
+            n * 2
+        }, |piece| {
+            if let Piece::Done(result) = piece {
+                seen.push(result);
+            }
+            true
+        });
+
+        assert_eq!((0..50).map(|n| n * 2).collect::<Vec<_>>(), seen);
+    }
+
+    #[test]
+    fn output_comes_back_in_order() {
+        let items: Vec<usize> = (0..20).collect();
+        // Every third item writes more than a few chunks' worth
+        let output = |n: usize| {
+            let len = if n.is_multiple_of(3) { 3 * CHUNK + 5 } else { 10 };
+            vec![b'a' + n as u8; len]
+        };
+        let mut seen = Vec::new();
+        let mut before_first_result = 0;
+        ordered_map(&items, 4, |&n, out| {
+            thread::sleep(Duration::from_millis((20 - n as u64) % 5));
+            for piece in output(n).chunks(1000) {
+                out.write_all(piece).unwrap();
+            }
+        }, |piece| {
+            match piece {
+                Piece::Output(output) => seen.extend(output),
+                Piece::Done(()) if before_first_result == 0 => before_first_result = seen.len(),
+                Piece::Done(()) => {}
+            }
+            true
+        });
+
+        assert_eq!(items.iter().flat_map(|&n| output(n)).collect::<Vec<_>>(), seen);
+        // The first item's output was passed on while it was still being written
+        assert_eq!(output(0).len(), before_first_result);
+    }
+
+    #[test]
+    fn workers_stay_close_to_the_head() {
+        let items: Vec<usize> = (0..100).collect();
+        let highest = AtomicUsize::new(0);
+        let mut while_first = 0;
+        ordered_map(&items, 4, |&n, _| {
+            highest.fetch_max(n, Ordering::Relaxed);
+            if n == 0 {
+                thread::sleep(Duration::from_millis(50));
+                return Some(highest.load(Ordering::Relaxed));
+            }
+            None
+        }, |piece| {
+            if let Piece::Done(Some(highest)) = piece {
+                while_first = highest;
+            }
+            true
+        });
+
+        // Nothing was started more than `2 * jobs` items past the first while it was searched
+        assert!(while_first < 8, "started item {} while the first was searched", while_first);
+    }
+
+    #[test]
+    fn stops_when_asked() {
+        let items: Vec<u32> = (0..1000).collect();
+        let mut seen = Vec::new();
+        ordered_map(&items, 4, |&n, _| n, |piece| match piece {
+            Piece::Done(result) => {
+                seen.push(result);
+                result < 9
+            }
+            Piece::Output(_) => true,
+        });
+
+        assert_eq!((0..10).collect::<Vec<_>>(), seen);
+    }
+
+    #[test]
+    fn no_items() {
+        let items: Vec<u32> = Vec::new();
+        ordered_map(&items, 4, |&n, _| n, |_| panic!("nothing to emit"));
+    }
+}
@@ -0,0 +1,194 @@
+// `--replace` with `--in-place`: rewriting files rather than printing them
+// A file is rewritten by writing its new contents to a temporary file next to it and renaming
+// that over the original, so anyone reading the file (or minigrep dying half way through) sees
+// either all of the old contents or all of the new ones, never a mix.  `--dry-run` prints what
+// would change as a unified diff instead of touching anything
+use std::ffi::OsString;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::process;
+
+use crate::Matcher;
+
+// A file's contents after replacing every match, along with the lines that changed
+pub struct Rewrite {
+    pub contents: String,
+    pub changes: Vec<Change>,
+}
+
+pub struct Change {
+    pub line_number: usize,
+    pub old: String,
+    pub new: String,
+}
+
+// Apply `template` to every match in `contents`.  Line endings are left as they were
+pub fn rewrite(matcher: &Matcher, template: &str, contents: &str) -> Rewrite {
+    let mut rewritten = String::with_capacity(contents.len());
+    let mut changes = Vec::new();
+    for (index, raw) in contents.split_inclusive('\n').enumerate() {
+        let line = raw.strip_suffix('\n').unwrap_or(raw);
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        let (new, _) = matcher.replace(line, template);
+        rewritten.push_str(&new);
+        rewritten.push_str(&raw[line.len()..]);
+        if new != line {
+            changes.push(Change { line_number: index + 1, old: line.to_string(), new });
+        }
+    }
+
+    Rewrite { contents: rewritten, changes }
+}
+
+// Replace the contents of `path` with `contents` in one step, keeping its permissions.  A file
+// that doesn't exist yet is created with the default ones
+pub fn write_atomically(path: &Path, contents: &[u8]) -> io::Result<()> {
+    // Renaming over a symlink would replace the link, so it's the file it points to that gets
+    // rewritten, with the temporary file next to that
+    let path = match fs::canonicalize(path) {
+        Ok(real) => real,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => path.to_path_buf(),
+        Err(e) => return Err(e),
+    };
+    let path = path.as_path();
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let name = path.file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "not a file"))?;
+    // The rename is only atomic within a file system, hence the same directory.  The process id
+    // keeps two minigreps rewriting the same file from sharing a temporary file
+    let mut temp_name = OsString::from(".");
+    temp_name.push(name);
+    temp_name.push(format!(".minigrep-{}", process::id()));
+    let temp = dir.join(temp_name);
+
+    let result = (|| {
+        let mut file = OpenOptions::new().write(true).create_new(true).open(&temp)?;
+        file.write_all(contents)?;
+        match fs::metadata(path) {
+            Ok(metadata) => file.set_permissions(metadata.permissions())?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        file.sync_all()?;
+        fs::rename(&temp, path)
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+
+    result
+}
+
+// Print `changes` to `path` as a unified diff without context lines (like `diff -U0`)
+pub fn write_diff(out: &mut impl Write, path: &Path, changes: &[Change]) -> io::Result<()> {
+    if changes.is_empty() {
+        return Ok(());
+    }
+
+    writeln!(out, "--- {}", path.display())?;
+    writeln!(out, "+++ {}", path.display())?;
+    // A replacement containing a newline adds lines, which moves every later hunk down
+    let mut added: isize = 0;
+    for hunk in changes.chunk_by(|a, b| a.line_number + 1 == b.line_number) {
+        let start = hunk[0].line_number;
+        let new_lines: Vec<&str> = hunk.iter().flat_map(|change| change.new.split('\n')).collect();
+        writeln!(
+            out,
+            "@@ -{} +{} @@",
+            hunk_range(start, hunk.len()),
+            hunk_range(start.saturating_add_signed(added), new_lines.len()),
+        )?;
+        for change in hunk {
+            writeln!(out, "-{}", change.old)?;
+        }
+        for line in &new_lines {
+            writeln!(out, "+{}", line)?;
+        }
+        added += new_lines.len() as isize - hunk.len() as isize;
+    }
+
+    Ok(())
+}
+
+// `start,len` as a hunk header writes it, where a length of one is left out
+fn hunk_range(start: usize, len: usize) -> String {
+    if len == 1 {
+        start.to_string()
+    } else {
+        format!("{},{}", start, len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MatcherOptions;
+
+    fn regex(pattern: &str) -> Matcher {
+        Matcher::new(pattern, &MatcherOptions { regex: true, ..MatcherOptions::default() }).unwrap()
+    }
+
+    #[test]
+    fn rewrite_keeps_line_endings() {
+        let rewrite = rewrite(&regex(r"(\w+)\(\)"), "$1(ctx)", "a()\r\nb\nc() + d()");
+
+        assert_eq!("a(ctx)\r\nb\nc(ctx) + d(ctx)", rewrite.contents);
+        let changed: Vec<usize> = rewrite.changes.iter().map(|c| c.line_number).collect();
+        assert_eq!(vec![1, 3], changed);
+    }
+
+    #[test]
+    fn diff_hunks() {
+        let rewrite = rewrite(&regex("x"), "y\nz", "x\nx\na\nx\n");
+        let mut out = Vec::new();
+        write_diff(&mut out, Path::new("f.txt"), &rewrite.changes).unwrap();
+
+        assert_eq!("\
+--- f.txt
++++ f.txt
+@@ -1,2 +1,4 @@
+-x
+-x
++y
++z
++y
++z
+@@ -4 +6,2 @@
+-x
++y
++z
+", String::from_utf8(out).unwrap());
+    }
+
+    #[test]
+    fn atomic_write() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("a.txt");
+        fs::write(&path, "old").unwrap();
+        write_atomically(&path, b"new").unwrap();
+
+        assert_eq!("new", fs::read_to_string(&path).unwrap());
+        // No temporary file is left behind
+        assert_eq!(1, fs::read_dir(dir.path()).unwrap().count());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn atomic_write_through_a_symlink() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("real")).unwrap();
+        let real = dir.path().join("real/a.txt");
+        let link = dir.path().join("link.txt");
+        fs::write(&real, "old").unwrap();
+        std::os::unix::fs::symlink(&real, &link).unwrap();
+        write_atomically(&link, b"new").unwrap();
+
+        assert_eq!("new", fs::read_to_string(&real).unwrap());
+        assert!(fs::symlink_metadata(&link).unwrap().file_type().is_symlink());
+        assert_eq!(1, fs::read_dir(dir.path().join("real")).unwrap().count());
+    }
+}
@@ -0,0 +1,396 @@
+// Searching from other programs
+// `run` prints what it finds, which is what the command line wants but not what a program using
+// minigrep as a library does.  A Searcher is configured once with a SearcherBuilder and then hands
+// out matches as an iterator of owned records.  Nothing happens until the iterator is advanced:
+// directories are walked when the search gets to them, files are opened one at a time and read a
+// line at a time, so stopping early (eg: with `.take(10)`) leaves the rest of the tree untouched
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use crate::walk::{self, GlobFilter, WalkOptions};
+use crate::{decompressed, looks_binary, Matcher, MatcherOptions};
+
+// Collects the options for a Searcher.  The defaults match the command line's: plain, case
+// sensitive queries, with hidden and ignored files skipped
+#[derive(Default)]
+pub struct SearcherBuilder {
+    patterns: Vec<String>,
+    matcher: MatcherOptions,
+    walk: WalkOptions,
+    text: bool,
+    decompress: bool,
+}
+
+impl SearcherBuilder {
+    pub fn new() -> SearcherBuilder {
+        SearcherBuilder::default()
+    }
+
+    // Look for `pattern`.  Lines matching any of the patterns added are selected, like `-e`
+    pub fn pattern(mut self, pattern: impl Into<String>) -> SearcherBuilder {
+        self.patterns.push(pattern.into());
+        self
+    }
+
+    pub fn patterns<S>(mut self, patterns: impl IntoIterator<Item = S>) -> SearcherBuilder
+    where
+        S: Into<String>,
+    {
+        self.patterns.extend(patterns.into_iter().map(Into::into));
+        self
+    }
+
+    // Treat the patterns as regular expressions
+    pub fn regex(mut self, yes: bool) -> SearcherBuilder {
+        self.matcher.regex = yes;
+        self
+    }
+
+    pub fn ignore_case(mut self, yes: bool) -> SearcherBuilder {
+        self.matcher.ignore_case = yes;
+        self
+    }
+
+    pub fn whole_word(mut self, yes: bool) -> SearcherBuilder {
+        self.matcher.whole_word = yes;
+        self
+    }
+
+    // Select the lines that don't match instead
+    pub fn invert(mut self, yes: bool) -> SearcherBuilder {
+        self.matcher.invert = yes;
+        self
+    }
+
+    // Match up to `max_edits` away from the patterns.  Records then carry how many edits each
+    // line needed, but come in file order rather than closest first
+    pub fn fuzzy(mut self, max_edits: Option<usize>) -> SearcherBuilder {
+        self.matcher.fuzzy = max_edits;
+        self
+    }
+
+    pub fn hidden(mut self, yes: bool) -> SearcherBuilder {
+        self.walk.hidden = yes;
+        self
+    }
+
+    // Respect `.gitignore` and `.ignore` files while walking directories (the default)
+    pub fn ignore_files(mut self, yes: bool) -> SearcherBuilder {
+        self.walk.ignore_files = yes;
+        self
+    }
+
+    pub fn globs(mut self, globs: GlobFilter) -> SearcherBuilder {
+        self.walk.globs = globs;
+        self
+    }
+
+    // Search binary files as if they were text.  Otherwise they are skipped
+    pub fn text(mut self, yes: bool) -> SearcherBuilder {
+        self.text = yes;
+        self
+    }
+
+    // Search the contents of compressed files, like `-z`
+    pub fn search_zip(mut self, yes: bool) -> SearcherBuilder {
+        self.decompress = yes;
+        self
+    }
+
+    // Only fails if a regular expression is invalid
+    pub fn build(self) -> Result<Searcher, regex::Error> {
+        Ok(Searcher {
+            matcher: Matcher::with_patterns(&self.patterns, &self.matcher)?,
+            walk: self.walk,
+            text: self.text,
+            decompress: self.decompress,
+        })
+    }
+}
+
+pub struct Searcher {
+    matcher: Matcher,
+    walk: WalkOptions,
+    text: bool,
+    decompress: bool,
+}
+
+// A selected line.  Unlike Match this owns its line, so it can outlive the file being read
+#[derive(Clone, Debug, PartialEq)]
+pub struct MatchRecord {
+    // The file the line came from, or None when searching a reader
+    pub path: Option<PathBuf>,
+    pub line_number: usize,
+    pub byte_offset: usize,
+    pub line: String,
+    // The byte ranges of every match in the line.  Empty for a line selected with `invert`
+    pub matches: Vec<Range<usize>>,
+    // How many edits the closest match needed, which is only ever non-zero with `fuzzy`
+    pub edits: usize,
+}
+
+// A file that couldn't be searched, or part of a directory that couldn't be walked.  The
+// iterator carries on with the next file afterwards
+#[derive(Debug)]
+pub struct SearchError {
+    pub path: Option<PathBuf>,
+    pub error: io::Error,
+}
+
+impl fmt::Display for SearchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.path {
+            Some(path) => write!(f, "{}: {}", path.display(), self.error),
+            None => write!(f, "{}", self.error),
+        }
+    }
+}
+
+impl Error for SearchError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+impl Searcher {
+    // The selected lines of every file in `paths`, searching directories recursively
+    pub fn search_paths<P: AsRef<Path>>(&self, paths: impl IntoIterator<Item = P>) -> Matches<'_> {
+        let paths = paths.into_iter().map(|path| path.as_ref().to_path_buf()).collect();
+        Matches { searcher: self, paths, sources: VecDeque::new(), current: None }
+    }
+
+    // The selected lines read from `reader`
+    pub fn search_reader<'a>(&'a self, reader: impl BufRead + 'a) -> Matches<'a> {
+        let mut sources = VecDeque::new();
+        sources.push_back(Source::Reader(Box::new(reader)));
+        Matches { searcher: self, paths: VecDeque::new(), sources, current: None }
+    }
+
+    // Get `reader` ready to be searched, or None if it's a binary file we should skip
+    fn prepare<'a>(
+        &self,
+        reader: Box<dyn BufRead + 'a>,
+    ) -> io::Result<Option<Box<dyn BufRead + 'a>>> {
+        let (mut reader, _) = decompressed(reader, self.decompress)?;
+        if !self.text && looks_binary(&mut reader)? {
+            return Ok(None);
+        }
+        Ok(Some(reader))
+    }
+}
+
+// Something to search that hasn't been opened yet, or an error found while walking a directory
+enum Source<'a> {
+    File(PathBuf),
+    Reader(Box<dyn BufRead + 'a>),
+    Failed(SearchError),
+}
+
+// The file being read
+struct Current<'a> {
+    path: Option<PathBuf>,
+    reader: Box<dyn BufRead + 'a>,
+    buf: Vec<u8>,
+    line_number: usize,
+    byte_offset: usize,
+}
+
+impl Current<'_> {
+    // The next selected line, or None at the end of the file
+    fn next_match(&mut self, matcher: &Matcher) -> io::Result<Option<MatchRecord>> {
+        loop {
+            self.buf.clear();
+            let read = self.reader.read_until(b'\n', &mut self.buf)?;
+            if read == 0 {
+                return Ok(None);
+            }
+            self.line_number += 1;
+            let byte_offset = self.byte_offset;
+            self.byte_offset += read;
+
+            let decoded = String::from_utf8_lossy(&self.buf);
+            let line = decoded.strip_suffix('\n').unwrap_or(&decoded);
+            let line = line.strip_suffix('\r').unwrap_or(line);
+            let found = matcher.matches(line);
+            if found.is_empty() == matcher.invert {
+                return Ok(Some(MatchRecord {
+                    path: self.path.clone(),
+                    line_number: self.line_number,
+                    byte_offset,
+                    line: line.to_string(),
+                    edits: found.iter().map(|(_, edits)| *edits).min().unwrap_or(0),
+                    matches: found.into_iter().map(|(range, _)| range).collect(),
+                }));
+            }
+        }
+    }
+}
+
+// The iterator returned by a Searcher
+pub struct Matches<'a> {
+    searcher: &'a Searcher,
+    // Paths given to `search_paths` that haven't been looked at yet
+    paths: VecDeque<PathBuf>,
+    // What's left to search from the path being worked through
+    sources: VecDeque<Source<'a>>,
+    current: Option<Current<'a>>,
+}
+
+impl Iterator for Matches<'_> {
+    type Item = Result<MatchRecord, SearchError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(current) = &mut self.current {
+                match current.next_match(&self.searcher.matcher) {
+                    Ok(Some(record)) => return Some(Ok(record)),
+                    Ok(None) => self.current = None,
+                    Err(error) => {
+                        let path = current.path.take();
+                        self.current = None;
+                        return Some(Err(SearchError { path, error }));
+                    }
+                }
+            } else if let Some(source) = self.sources.pop_front() {
+                let (path, reader): (_, Box<dyn BufRead>) = match source {
+                    Source::File(path) => match File::open(&path) {
+                        Ok(file) => (Some(path), Box::new(BufReader::new(file))),
+                        Err(error) => return Some(Err(SearchError { path: Some(path), error })),
+                    },
+                    Source::Reader(reader) => (None, reader),
+                    Source::Failed(error) => return Some(Err(error)),
+                };
+                match self.searcher.prepare(reader) {
+                    Ok(Some(reader)) => {
+                        self.current = Some(Current {
+                            path,
+                            reader,
+                            buf: Vec::new(),
+                            line_number: 0,
+                            byte_offset: 0,
+                        });
+                    }
+                    Ok(None) => {}
+                    Err(error) => return Some(Err(SearchError { path, error })),
+                }
+            } else {
+                let path = self.paths.pop_front()?;
+                let walk = &self.searcher.walk;
+                if path.is_dir() {
+                    let (files, errors) = walk::files(&path, walk);
+                    self.sources.extend(errors.into_iter().map(|walk::WalkError { path, error }| {
+                        Source::Failed(SearchError { path, error })
+                    }));
+                    self.sources.extend(files.into_iter().map(Source::File));
+                } else if walk.globs.allows(&path) {
+                    self.sources.push_back(Source::File(path));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    const POEM: &str = "I'm nobody! Who are you?\nAre you nobody, too?\nThen there's a pair of us\n";
+
+    #[test]
+    fn records_from_a_reader() {
+        let searcher = SearcherBuilder::new().pattern("nobody").build().unwrap();
+        let records: Vec<MatchRecord> =
+            searcher.search_reader(POEM.as_bytes()).map(Result::unwrap).collect();
+
+        assert_eq!(vec![
+            MatchRecord {
+                path: None,
+                line_number: 1,
+                byte_offset: 0,
+                line: "I'm nobody! Who are you?".to_string(),
+                matches: vec![Range { start: 4, end: 10 }],
+                edits: 0,
+            },
+            MatchRecord {
+                path: None,
+                line_number: 2,
+                byte_offset: 25,
+                line: "Are you nobody, too?".to_string(),
+                matches: vec![Range { start: 8, end: 14 }],
+                edits: 0,
+            },
+        ], records);
+    }
+
+    #[test]
+    fn options_are_passed_on() {
+        let searcher = SearcherBuilder::new()
+            .patterns(["THEN", "who"])
+            .ignore_case(true)
+            .invert(true)
+            .build()
+            .unwrap();
+        let lines: Vec<usize> = searcher.search_reader(POEM.as_bytes())
+            .map(|record| record.unwrap().line_number)
+            .collect();
+
+        assert_eq!(vec![2], lines);
+        assert!(SearcherBuilder::new().pattern("(").regex(true).build().is_err());
+    }
+
+    #[test]
+    fn searches_lazily() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("a.txt"), POEM).unwrap();
+        fs::write(dir.path().join("b.bin"), "nobody\0").unwrap();
+        let searcher = SearcherBuilder::new().pattern("nobody").build().unwrap();
+
+        let paths = [dir.path().join("a.txt"), dir.path().join("missing")];
+        let mut matches = searcher.search_paths(&paths);
+        assert_eq!(1, matches.next().unwrap().unwrap().line_number);
+        // Nothing past the first file has been looked at yet
+        assert_eq!(1, matches.paths.len());
+        assert_eq!(2, matches.next().unwrap().unwrap().line_number);
+        let error = matches.next().unwrap().unwrap_err();
+        assert_eq!(Some(dir.path().join("missing")), error.path);
+        assert!(matches.next().is_none());
+
+        // Binary files are skipped unless asked for
+        let found = searcher.search_paths([dir.path()]).count();
+        assert_eq!(2, found);
+        let searcher = SearcherBuilder::new().pattern("nobody").text(true).build().unwrap();
+        assert_eq!(3, searcher.search_paths([dir.path()]).count());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn walk_errors_are_yielded() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let locked = dir.path().join("locked");
+        fs::create_dir(&locked).unwrap();
+        fs::write(dir.path().join("a.txt"), POEM).unwrap();
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o000)).unwrap();
+        // Permissions don't stop root
+        let readable = fs::read_dir(&locked).is_ok();
+        let searcher = SearcherBuilder::new().pattern("nobody").build().unwrap();
+        let results: Vec<_> = searcher.search_paths([dir.path()]).collect();
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o755)).unwrap();
+        if readable {
+            return;
+        }
+
+        assert_eq!(3, results.len());
+        let error = results[0].as_ref().unwrap_err();
+        assert_eq!(Some(locked), error.path);
+        assert_eq!(io::ErrorKind::PermissionDenied, error.error.kind());
+        assert!(results[1..].iter().all(Result::is_ok));
+    }
+}
@@ -0,0 +1,213 @@
+// Configuration files
+// Defaults are read from `~/.config/minigrep/config.toml` (or `$XDG_CONFIG_HOME/minigrep/...`)
+// and then from the nearest `.minigreprc` in the current directory or one of its parents, so a
+// project can adjust what a user prefers everywhere.  Both are TOML where every key is the long
+// name of a flag:
+//
+//     ignore-case = true
+//     include = ["*.rs", "*.toml"]
+//     context = 2
+//     colors = { match = "1;32" }
+//
+//     [preset.rust]
+//     include = ["*.rs"]
+//     word-regexp = true
+//
+// Rather than a second set of options with its own rules, each setting is turned back into the
+// flag it names and put in front of the command line, where the parser deals with it like any
+// other flag.  That way the command line wins where flags can be overridden: the last of `-i`
+// and `-s` counts, a later `-C` replaces an earlier one, and globs add up.  A setting that only
+// turns something on (eg: `hidden = true`) can't be turned off again except with `--no-config`,
+// which is why flags whose effect is hard to notice, or that would conflict with flags on the
+// command line, aren't allowed (see `cli::takes_value`).  A preset is a named bundle of settings
+// that `--preset NAME` puts in place of itself on the command line
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::cli::{self, ArgsError};
+
+pub const GLOBAL_FILE: &str = "minigrep/config.toml";
+pub const LOCAL_FILE: &str = ".minigreprc";
+
+// Settings turned into flags
+#[derive(Debug, Default, PartialEq)]
+pub struct Settings {
+    pub defaults: Vec<String>,
+    pub presets: HashMap<String, Vec<String>>,
+}
+
+impl Settings {
+    // The user's settings, then the project's on top
+    pub fn load() -> Result<Settings, ArgsError> {
+        let mut settings = Settings::default();
+        let global = env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")));
+        if let Some(dir) = global {
+            settings.merge(Settings::read(&dir.join(GLOBAL_FILE))?);
+        }
+        let cwd = env::current_dir().unwrap_or_default();
+        let mut local = cwd.ancestors().map(|dir| dir.join(LOCAL_FILE));
+        if let Some(local) = local.find(|path| path.is_file()) {
+            settings.merge(Settings::read(&local)?);
+        }
+
+        Ok(settings)
+    }
+
+    // A file that doesn't exist has no settings
+    fn read(path: &Path) -> Result<Settings, ArgsError> {
+        let invalid = |message: String| ArgsError::Settings { path: path.to_path_buf(), message };
+        match fs::read_to_string(path) {
+            Ok(text) => Settings::parse(&text).map_err(invalid),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Settings::default()),
+            Err(e) => Err(invalid(e.to_string())),
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Settings, String> {
+        let table: toml::Table =
+            text.parse().map_err(|e: toml::de::Error| e.message().to_string())?;
+        let mut settings = Settings::default();
+        for (key, value) in &table {
+            if key != "preset" {
+                settings.defaults.extend(flags(key, value)?);
+                continue;
+            }
+
+            let presets = value.as_table().ok_or("'preset' should be a table of presets")?;
+            for (name, preset) in presets {
+                let preset = preset.as_table()
+                    .ok_or_else(|| format!("preset '{}' should be a table", name))?;
+                let mut args = Vec::new();
+                for (key, value) in preset {
+                    args.extend(flags(key, value)?);
+                }
+                settings.presets.insert(name.clone(), args);
+            }
+        }
+
+        Ok(settings)
+    }
+
+    // Later settings go after earlier ones, so they win.  A preset defined again is replaced
+    fn merge(&mut self, other: Settings) {
+        self.defaults.extend(other.defaults);
+        self.presets.extend(other.presets);
+    }
+}
+
+// The flags a single setting stands for
+fn flags(key: &str, value: &toml::Value) -> Result<Vec<String>, String> {
+    let takes_value = cli::takes_value(key)
+        .ok_or_else(|| format!("'{}' isn't a flag that can be set here", key))?;
+    let wrong_type = || {
+        let expected = if takes_value { "a value" } else { "true or false" };
+        format!("'{}' should be {}", key, expected)
+    };
+
+    let values: Vec<String> = match value {
+        toml::Value::Boolean(on) if !takes_value => {
+            return Ok(if *on { vec![format!("--{}", key)] } else { Vec::new() });
+        }
+        toml::Value::String(s) if takes_value => vec![s.clone()],
+        toml::Value::Integer(n) if takes_value => vec![n.to_string()],
+        // Flags that can be given more than once (eg: `include`)
+        toml::Value::Array(values) if takes_value => values.iter()
+            .map(|value| scalar(value).ok_or_else(wrong_type))
+            .collect::<Result<_, _>>()?,
+        // `colors = { match = "1;32" }` is `--colors=match=1;32`
+        toml::Value::Table(table) if takes_value => table.iter()
+            .map(|(name, value)| {
+                scalar(value).map(|value| format!("{}={}", name, value)).ok_or_else(wrong_type)
+            })
+            .collect::<Result<_, _>>()?,
+        _ => return Err(wrong_type()),
+    };
+
+    Ok(values.into_iter().map(|value| format!("--{}={}", key, value)).collect())
+}
+
+fn scalar(value: &toml::Value) -> Option<String> {
+    match value {
+        toml::Value::String(s) => Some(s.clone()),
+        toml::Value::Integer(n) => Some(n.to_string()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn settings_become_flags() {
+        let settings = Settings::parse(r#"
+            ignore-case = true
+            hidden = false
+            context = 2
+            include = ["*.rs", "*.toml"]
+            colors = { match = "1;32" }
+
+            [preset.rust]
+            word-regexp = true
+            exclude = "target"
+        "#).unwrap();
+
+        assert_eq!(
+            vec![
+                "--colors=match=1;32",
+                "--context=2",
+                "--ignore-case",
+                "--include=*.rs",
+                "--include=*.toml",
+            ],
+            settings.defaults
+        );
+        assert_eq!(vec!["--exclude=target", "--word-regexp"], settings.presets["rust"]);
+    }
+
+    #[test]
+    fn invalid_settings() {
+        assert!(Settings::parse("ignore-case = ").is_err());
+        assert_eq!(
+            Err("'frobnicate' isn't a flag that can be set here".to_string()),
+            Settings::parse("frobnicate = true")
+        );
+        assert_eq!(Err("'context' should be a value".to_string()), Settings::parse("context = true"));
+        assert_eq!(
+            Err("'hidden' should be true or false".to_string()),
+            Settings::parse("hidden = \"yes\"")
+        );
+        assert!(Settings::parse("help = true").is_err());
+        // Nothing that writes to files or changes which lines are selected
+        for setting in [
+            "replace = \"x\"",
+            "in-place = true",
+            "dry-run = true",
+            "watch = true",
+            "invert-match = true",
+            "count = true",
+            "files-with-matches = true",
+            "files-without-match = true",
+            "field = \"level\"",
+            "csv-column = 2",
+        ] {
+            assert!(Settings::parse(setting).is_err(), "{}", setting);
+        }
+        assert!(Settings::parse("[preset.p]\nreplace = \"x\"").is_err());
+        assert!(Settings::parse("preset = 1").is_err());
+    }
+
+    #[test]
+    fn later_files_win() {
+        let mut settings = Settings::parse("hidden = true\n[preset.a]\nhidden = true").unwrap();
+        settings.merge(Settings::parse("regex = true\n[preset.a]\nregex = true").unwrap());
+
+        assert_eq!(vec!["--hidden", "--regex"], settings.defaults);
+        assert_eq!(vec!["--regex"], settings.presets["a"]);
+    }
+}
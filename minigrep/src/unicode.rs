@@ -0,0 +1,184 @@
+// Unicode helpers for matching: full case folding and word boundaries
+//
+// Lowercasing a line to compare it against a lowercased query (what minigrep used to do) goes
+// wrong in two ways.  Some characters change length when their case changes (`İ` is two bytes
+// but lowercases to three), so positions in the lowercased copy don't line up with the original
+// line any more.  And lowercasing isn't the right comparison to begin with: `ß` and `SS` are the
+// same word without either being the lowercase of the other.  Unicode's full case folding maps
+// both to `ss`, so we fold instead and keep track of which original character every folded byte
+// came from to report offsets in the line as it really is
+use std::ops::Range;
+
+use caseless::Caseless;
+use unicode_segmentation::UnicodeSegmentation;
+
+// Fold the case of a whole string, eg: for the query
+pub fn fold(text: &str) -> String {
+    text.chars().default_case_fold().collect()
+}
+
+// A line with its case folded, remembering where each folded character came from
+pub struct FoldedLine {
+    folded: String,
+    // For every character of the original line, (its byte offset in the line, the byte offset
+    // where its folding starts in `folded`).  Both columns only ever increase
+    starts: Vec<(usize, usize)>,
+    len: usize,
+}
+
+impl FoldedLine {
+    pub fn new(line: &str) -> FoldedLine {
+        let mut folded = String::with_capacity(line.len());
+        let mut starts = Vec::with_capacity(line.len());
+        for (offset, c) in line.char_indices() {
+            starts.push((offset, folded.len()));
+            folded.extend(std::iter::once(c).default_case_fold());
+        }
+
+        FoldedLine { folded, starts, len: line.len() }
+    }
+
+    // Find a match starting at byte `from` of the original line.  `search(folded, at)` looks for
+    // the folded queries (see `fold`) in the folded line starting at byte `at` of it
+    // A match has to cover whole characters of the original line: searching for `s` will not
+    // find half of a `ß`
+    pub fn find(
+        &self,
+        from: usize,
+        mut search: impl FnMut(&str, usize) -> Option<Range<usize>>,
+    ) -> Option<Range<usize>> {
+        let mut at = self.to_folded(from)?;
+        while at <= self.folded.len() {
+            let Range { start, end } = search(&self.folded, at)?;
+            if let (Some(orig_start), Some(orig_end)) =
+                (self.to_original(start), self.to_original(end))
+            {
+                return Some(orig_start..orig_end);
+            }
+            // Step over the character the rejected match started on
+            at = start + self.folded[start..].chars().next().map_or(1, |c| c.len_utf8());
+        }
+
+        None
+    }
+
+    // The offset in `folded` of original offset `offset`, which must be a character boundary
+    fn to_folded(&self, offset: usize) -> Option<usize> {
+        if offset == self.len {
+            return Some(self.folded.len());
+        }
+        self.starts.binary_search_by_key(&offset, |&(orig, _)| orig)
+            .ok()
+            .map(|index| self.starts[index].1)
+    }
+
+    // The original offset of folded offset `offset`, if it falls between two original characters
+    fn to_original(&self, offset: usize) -> Option<usize> {
+        if offset == self.folded.len() {
+            return Some(self.len);
+        }
+        self.starts.binary_search_by_key(&offset, |&(_, folded)| folded)
+            .ok()
+            .map(|index| self.starts[index].0)
+    }
+}
+
+// Whether `range` of `line` starts and ends on a word boundary as defined by Unicode (UAX #29),
+// which is what `-w` asks for.  Unlike checking the characters on either side against `\w`, this
+// knows that `can't` is a single word and that a combining accent belongs to the letter before it
+pub fn is_whole_word(line: &str, range: &Range<usize>) -> bool {
+    is_boundary(line, range.start) && is_boundary(line, range.end)
+}
+
+// How far from an offset `is_boundary` looks for somewhere to start and stop segmenting
+const WINDOW: usize = 256;
+
+// Whether `offset` is a word boundary of `text`.  Segmenting all of `text` for every check would
+// make `-U`, where the text is a whole file, quadratic.  Instead only the part around `offset` is
+// segmented, starting and stopping just after whitespace that's followed by some other ASCII
+// character: there is always a boundary there, and none of the rules for what's on either side
+// of a boundary can see past it.  Only a run of WINDOW bytes without such a place falls back to
+// segmenting from the start
+fn is_boundary(text: &str, offset: usize) -> bool {
+    if offset == 0 || offset == text.len() {
+        return true;
+    }
+    let bytes = text.as_bytes();
+    let safe = |i: usize| {
+        bytes[i - 1].is_ascii_whitespace() && bytes[i].is_ascii() && !bytes[i].is_ascii_whitespace()
+    };
+    let start = (offset.saturating_sub(WINDOW).max(1)..=offset)
+        .rev()
+        .find(|&i| safe(i))
+        .unwrap_or(0);
+    let end = (offset + 1..text.len().min(offset + WINDOW))
+        .find(|&i| safe(i))
+        .unwrap_or(text.len());
+
+    text[start..end].split_word_bound_indices().any(|(at, _)| start + at == offset)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    impl FoldedLine {
+        fn find_str(&self, query: &str, from: usize) -> Option<Range<usize>> {
+            self.find(from, |folded, at| {
+                folded[at..].find(query).map(|start| at + start..at + start + query.len())
+            })
+        }
+    }
+
+    #[test]
+    fn full_case_folding() {
+        let line = FoldedLine::new("Die STRASSE heißt Straße");
+
+        assert_eq!(Some(4..11), line.find_str(&fold("straße"), 0));
+        assert_eq!(Some(19..26), line.find_str(&fold("STRASSE"), 5));
+        assert_eq!(Some(12..18), line.find_str(&fold("HEISST"), 0));
+    }
+
+    #[test]
+    fn offsets_survive_length_changes() {
+        // `İ` is two bytes in the line but folds to three
+        let line = FoldedLine::new("İstanbul x");
+
+        assert_eq!(Some(10..11), line.find_str(&fold("X"), 0));
+    }
+
+    #[test]
+    fn no_partial_characters() {
+        let line = FoldedLine::new("ß s");
+
+        assert_eq!(Some(3..4), line.find_str("s", 0));
+        assert_eq!(Some(0..2), line.find_str("ss", 0));
+    }
+
+    #[test]
+    fn empty_query() {
+        assert_eq!(Some(0..0), FoldedLine::new("abc").find_str("", 0));
+        assert_eq!(Some(0..0), FoldedLine::new("").find_str("", 0));
+    }
+
+    #[test]
+    fn word_boundaries() {
+        assert!(is_whole_word("rust: fast", &(0..4)));
+        assert!(!is_whole_word("rusty nail", &(0..4)));
+        assert!(!is_whole_word("trust me", &(1..5)));
+        assert!(!is_whole_word("can't stop", &(0..3)));
+        // The combining acute accent belongs to the `e` before it
+        assert!(!is_whole_word("cafe\u{301} au lait", &(0..4)));
+        assert!(is_whole_word("naïve idea", &(0..6)));
+    }
+
+    #[test]
+    fn boundaries_from_part_of_the_text() {
+        let text = "It's 3.14, isn't it?\n  cafe\u{301} 🇫🇷🇩🇪 a.b  x\u{200d}y\n\n$x + e.g. \"quoted\" "
+            .repeat(20);
+        let all: Vec<usize> = text.split_word_bound_indices().map(|(at, _)| at).collect();
+        for offset in (0..text.len()).filter(|&offset| text.is_char_boundary(offset)) {
+            assert_eq!(all.contains(&offset), is_boundary(&text, offset), "{}", offset);
+        }
+    }
+}
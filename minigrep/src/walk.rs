@@ -0,0 +1,265 @@
+// Walking directories for minigrep
+// The `ignore` crate does the heavy lifting here: it understands `.gitignore` and `.ignore` files
+// (including ones in parent directories) as well as skipping hidden files and directories
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use ignore::WalkBuilder;
+
+// Options controlling which files a directory walk will yield
+pub struct WalkOptions {
+    // Include files and directories whose name starts with a `.`
+    pub hidden: bool,
+    // Respect `.gitignore` and `.ignore` files
+    pub ignore_files: bool,
+    // `--include`/`--exclude` globs
+    pub globs: GlobFilter,
+}
+
+impl Default for WalkOptions {
+    fn default() -> WalkOptions {
+        WalkOptions { hidden: false, ignore_files: true, globs: GlobFilter::default() }
+    }
+}
+
+// The `--include` and `--exclude` filters
+// A glob is tried against both the file name and the whole path, so `*.rs` and `src/**/*.rs`
+// both do what you'd expect.  When any include globs are given a file has to match one of them,
+// and a file matching an exclude glob is always dropped
+#[derive(Clone, Default)]
+pub struct GlobFilter {
+    include: Option<GlobSet>,
+    exclude: Option<GlobSet>,
+}
+
+impl GlobFilter {
+    pub fn new(include: &[String], exclude: &[String]) -> Result<GlobFilter, globset::Error> {
+        Ok(GlobFilter { include: build_set(include)?, exclude: build_set(exclude)? })
+    }
+
+    // Should the file at `path` be searched?
+    pub fn allows(&self, path: &Path) -> bool {
+        if let Some(exclude) = &self.exclude {
+            if matches(exclude, path) {
+                return false;
+            }
+        }
+
+        match &self.include {
+            Some(include) => matches(include, path),
+            None => true,
+        }
+    }
+
+    // Directories are only ever pruned by exclude globs; include globs describe files
+    fn allows_dir(&self, path: &Path) -> bool {
+        match &self.exclude {
+            Some(exclude) => !matches(exclude, path),
+            None => true,
+        }
+    }
+}
+
+fn build_set(globs: &[String]) -> Result<Option<GlobSet>, globset::Error> {
+    if globs.is_empty() {
+        return Ok(None);
+    }
+
+    let mut builder = GlobSetBuilder::new();
+    for glob in globs {
+        builder.add(Glob::new(glob)?);
+    }
+
+    builder.build().map(Some)
+}
+
+fn matches(set: &GlobSet, path: &Path) -> bool {
+    // Strip a leading `./` so that `src/*.rs` matches when searching `.`
+    let path = path.strip_prefix(".").unwrap_or(path);
+
+    set.is_match(path) || path.file_name().is_some_and(|name| set.is_match(name))
+}
+
+// Something in a directory that couldn't be walked, eg: a subdirectory we may not read
+#[derive(Debug)]
+pub struct WalkError {
+    pub path: Option<PathBuf>,
+    pub error: io::Error,
+}
+
+impl WalkError {
+    fn new(error: ignore::Error) -> WalkError {
+        let path = path_of(&error);
+        let message = error.to_string();
+        match error.into_io_error() {
+            Some(error) => WalkError { path, error: unwrapped(error) },
+            // Anything else (eg: a bad glob in an ignore file) already says where it is
+            None => WalkError { path: None, error: io::Error::other(message) },
+        }
+    }
+}
+
+impl fmt::Display for WalkError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.path {
+            Some(path) => write!(f, "{}: {}", path.display(), self.error),
+            None => write!(f, "{}", self.error),
+        }
+    }
+}
+
+// The error from the system, without the message naming the path that it can come wrapped in
+fn unwrapped(error: io::Error) -> io::Error {
+    let inner = error.get_ref()
+        .and_then(|inner| inner.source())
+        .and_then(|source| source.downcast_ref::<io::Error>())
+        .and_then(io::Error::raw_os_error);
+    match error.raw_os_error().or(inner) {
+        Some(code) => io::Error::from_raw_os_error(code),
+        None => error,
+    }
+}
+
+fn path_of(error: &ignore::Error) -> Option<PathBuf> {
+    match error {
+        ignore::Error::WithPath { path, .. } => Some(path.clone()),
+        ignore::Error::WithDepth { err, .. } | ignore::Error::WithLineNumber { err, .. } => {
+            path_of(err)
+        }
+        _ => None,
+    }
+}
+
+// Recursively collect every regular file below `root`, along with the entries we couldn't read
+// (eg: permission denied).  Those are left to the caller to report, so that one bad directory
+// doesn't abort the search of an entire tree
+// Files are returned sorted by name so output is stable from run to run
+pub fn files(root: &Path, options: &WalkOptions) -> (Vec<PathBuf>, Vec<WalkError>) {
+    let walker = WalkBuilder::new(root)
+        .hidden(!options.hidden)
+        .ignore(options.ignore_files)
+        .git_ignore(options.ignore_files)
+        .git_global(options.ignore_files)
+        .git_exclude(options.ignore_files)
+        .parents(options.ignore_files)
+        // Honor .gitignore files even when the tree isn't a git checkout
+        .require_git(false)
+        .sort_by_file_name(|a, b| a.cmp(b))
+        .filter_entry({
+            let globs = options.globs.clone();
+            move |entry| {
+                // Never prune the directory the user asked us to search
+                let is_dir = entry.file_type().is_some_and(|t| t.is_dir());
+                !is_dir || entry.depth() == 0 || globs.allows_dir(entry.path())
+            }
+        })
+        .build();
+
+    let mut files = Vec::new();
+    let mut errors = Vec::new();
+    for entry in walker {
+        match entry {
+            Ok(entry) => {
+                if entry.file_type().is_some_and(|t| t.is_file()) && options.globs.allows(entry.path()) {
+                    files.push(entry.into_path());
+                }
+            }
+            Err(err) => errors.push(WalkError::new(err)),
+        }
+    }
+
+    (files, errors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn names(root: &Path, files: Vec<PathBuf>) -> Vec<String> {
+        files.iter()
+            .map(|f| f.strip_prefix(root).unwrap().to_string_lossy().replace('\\', "/"))
+            .collect()
+    }
+
+    fn tree() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("src/nested")).unwrap();
+        fs::create_dir_all(root.join("target")).unwrap();
+        fs::create_dir_all(root.join(".hidden_dir")).unwrap();
+        fs::write(root.join(".gitignore"), "target/\n").unwrap();
+        fs::write(root.join("src/.ignore"), "*.log\n").unwrap();
+        fs::write(root.join("src/lib.rs"), "").unwrap();
+        fs::write(root.join("src/debug.log"), "").unwrap();
+        fs::write(root.join("src/nested/mod.rs"), "").unwrap();
+        fs::write(root.join("target/out.rs"), "").unwrap();
+        fs::write(root.join(".hidden_dir/secret.rs"), "").unwrap();
+        fs::write(root.join(".env"), "").unwrap();
+        dir
+    }
+
+    #[test]
+    fn honors_ignore_files_and_hidden_rules() {
+        let dir = tree();
+        let (found, errors) = files(dir.path(), &WalkOptions::default());
+
+        assert_eq!(vec!["src/lib.rs", "src/nested/mod.rs"], names(dir.path(), found));
+        assert!(errors.is_empty());
+    }
+
+    #[test]
+    fn hidden_and_no_ignore() {
+        let dir = tree();
+        let options = WalkOptions { hidden: true, ignore_files: false, ..WalkOptions::default() };
+        let (found, _) = files(dir.path(), &options);
+
+        assert_eq!(
+            vec![
+                ".env",
+                ".gitignore",
+                ".hidden_dir/secret.rs",
+                "src/.ignore",
+                "src/debug.log",
+                "src/lib.rs",
+                "src/nested/mod.rs",
+                "target/out.rs",
+            ],
+            names(dir.path(), found)
+            );
+    }
+
+    #[test]
+    fn include_and_exclude_globs() {
+        let dir = tree();
+        let globs = GlobFilter::new(&["*.rs".to_string()], &["nested".to_string()]).unwrap();
+        let options = WalkOptions { globs, ..WalkOptions::default() };
+        let (found, _) = files(dir.path(), &options);
+
+        assert_eq!(vec!["src/lib.rs"], names(dir.path(), found));
+    }
+
+    #[test]
+    fn errors_are_returned() {
+        let dir = tree();
+        let missing = dir.path().join("missing");
+        let (found, errors) = files(&missing, &WalkOptions::default());
+
+        assert!(found.is_empty());
+        assert_eq!(1, errors.len());
+        assert_eq!(Some(missing), errors[0].path);
+        assert_eq!(io::ErrorKind::NotFound, errors[0].error.kind());
+    }
+
+    #[test]
+    fn globs_match_name_or_path() {
+        let globs = GlobFilter::new(&["src/*.rs".to_string()], &["*_test.rs".to_string()]).unwrap();
+
+        assert!(globs.allows(Path::new("./src/lib.rs")));
+        assert!(!globs.allows(Path::new("src/lib_test.rs")));
+        assert!(!globs.allows(Path::new("tests/lib.rs")));
+        assert!(GlobFilter::default().allows(Path::new("anything")));
+    }
+}
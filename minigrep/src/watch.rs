@@ -0,0 +1,240 @@
+// `--watch`: searching files again as they change, like `tail -f | grep`
+// After printing every match once, the searched paths are polled for files that were added or
+// modified.  A file that grew is assumed to have been appended to, like a log, so only its new
+// lines are read.  Any other change could be anywhere in the file, so it is searched again from
+// the start and only the matches that weren't there before are printed.  A match is identified
+// by its line number and text, which means a line that changed is printed again.  Only the
+// matches of a file's last search from the start are remembered for this, so that watching a busy
+// log doesn't take more and more memory: when it's rewritten, matches that were appended since
+// then are printed again as well
+// Polling rather than using inotify and friends keeps this portable and dependency free, and a
+// few stats every quarter of a second cost next to nothing
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::hash::{Hash, Hasher};
+use std::io::{self, BufRead, BufReader, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use crate::{display_name, highlights, inputs, json, looks_binary, select_field, write_selected};
+use crate::{BinaryMode, Config, Input, Match};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+// What we know about a file from the last time it was searched
+struct Watched {
+    stamp: Stamp,
+    // Where the first line we haven't finished with starts, and how many lines come before it.
+    // A last line without a line ending may still be being written, so it's only searched once
+    // it's finished
+    offset: u64,
+    line_number: usize,
+    // The matches found the last time the file was searched from the start, see `key`
+    seen: HashSet<u64>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+struct Stamp {
+    len: u64,
+    modified: Option<SystemTime>,
+}
+
+// Search the paths in `config` until our reader goes away.  Returns whether anything was found
+pub fn watch(config: &Config) -> io::Result<bool> {
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    let mut watched: HashMap<PathBuf, Watched> = HashMap::new();
+    // Files we couldn't search, so the error is only reported once
+    let mut failing: HashSet<PathBuf> = HashSet::new();
+    let mut found = false;
+    loop {
+        let (files, with_filename, _) = inputs(config);
+        for file in &files {
+            let state = watched.remove(&file.path);
+            match check(config, file, with_filename, state, &mut out) {
+                Ok((state, any)) => {
+                    found |= any;
+                    failing.remove(&file.path);
+                    watched.insert(file.path.clone(), state);
+                }
+                Err(e) if e.kind() == io::ErrorKind::BrokenPipe => return Ok(found),
+                Err(e) => {
+                    if failing.insert(file.path.clone()) {
+                        eprintln!("minigrep: {}: {}", display_name(&file.path).display(), e);
+                    }
+                }
+            }
+        }
+        // Files that are gone are forgotten, so they are searched from the start if they return
+        watched.retain(|path, _| files.iter().any(|file| &file.path == path));
+
+        match out.flush() {
+            Err(ref e) if e.kind() == io::ErrorKind::BrokenPipe => return Ok(found),
+            result => result?,
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+// Search whatever changed in `file` since it was last seen as `state`, printing the new matches.
+// Returns what we know about the file now and whether anything was printed
+fn check(
+    config: &Config,
+    file: &Input,
+    with_filename: bool,
+    state: Option<Watched>,
+    out: &mut impl Write,
+) -> io::Result<(Watched, bool)> {
+    let metadata = fs::metadata(&file.path)?;
+    let stamp = Stamp { len: metadata.len(), modified: metadata.modified().ok() };
+    // Appended lines are all new and leave `seen` as it is.  `previous` is what a file that has
+    // to be searched from the start is compared with
+    let (offset, mut line_number, previous, mut seen) = match state {
+        Some(state) if state.stamp == stamp => return Ok((state, false)),
+        Some(state) if stamp.len > state.stamp.len => {
+            (state.offset, state.line_number, None, state.seen)
+        }
+        Some(state) => (0, 0, Some(state.seen), HashSet::new()),
+        None => (0, 0, Some(HashSet::new()), HashSet::new()),
+    };
+
+    let mut reader = BufReader::new(File::open(&file.path)?);
+    reader.seek(SeekFrom::Start(offset))?;
+    if offset == 0 && config.binary != BinaryMode::Text && looks_binary(&mut reader)? {
+        // Binary files are skipped, and once they are only looked at again if they are replaced
+        let state = Watched { stamp, offset: stamp.len, line_number: 0, seen: HashSet::new() };
+        return Ok((state, false));
+    }
+
+    let label = display_name(&file.path);
+    let path = if with_filename { Some(label) } else { None };
+    let mut count = 0;
+    let mut byte_offset = offset;
+    let mut buf = Vec::new();
+    loop {
+        buf.clear();
+        let read = reader.read_until(b'\n', &mut buf)?;
+        if read == 0 {
+            break;
+        }
+        let decoded = String::from_utf8_lossy(&buf);
+        let line = decoded.strip_suffix('\n').unwrap_or(&decoded);
+        let line = line.strip_suffix('\r').unwrap_or(line);
+
+        // A line that is still being written is left for next time, like `tail -f | grep` would
+        if !buf.ends_with(b"\n") {
+            break;
+        }
+        let selected = match &config.field {
+            Some(field) => select_field(config, field, label, line_number + 1, line)
+                .map(|(found, highlights)| (found, Some(highlights))),
+            None => config.matcher.select(line).map(|found| (found, None)),
+        };
+        if let Some((found, highlighted)) = selected {
+            let new = match &previous {
+                Some(previous) => {
+                    let key = key(line_number + 1, line);
+                    seen.insert(key);
+                    !previous.contains(&key)
+                }
+                None => true,
+            };
+            if new {
+                if count == 0 && config.output.json {
+                    json::write_begin(out, label)?;
+                }
+                count += 1;
+                let m = Match {
+                    line_number: line_number + 1,
+                    byte_offset: byte_offset as usize,
+                    column: found.start + 1,
+                    line,
+                };
+                let edits = config.matcher.distance(line).filter(|_| config.matcher.is_fuzzy());
+                let highlighted = highlighted.unwrap_or_else(|| highlights(config, line));
+                write_selected(config, label, path, &m, &highlighted, edits, out)?;
+            }
+        }
+        line_number += 1;
+        byte_offset += read as u64;
+    }
+    if count > 0 && config.output.json {
+        json::write_end(out, label, count)?;
+    }
+
+    let state = Watched { stamp, offset: byte_offset, line_number, seen };
+    Ok((state, count > 0))
+}
+
+// What a printed match is remembered by
+fn key(line_number: usize, line: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    (line_number, line).hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    use crate::cli;
+    use crate::settings::Settings;
+
+    fn config(args: &[&str]) -> Config {
+        let mut all = vec!["minigrep".to_string()];
+        all.extend(args.iter().map(|arg| arg.to_string()));
+        cli::parse(all.into_iter(), false, &Settings::default()).unwrap()
+    }
+
+    // Check `path` once and return what was printed
+    fn poll(config: &Config, path: &Path, state: &mut Option<Watched>) -> String {
+        let file = Input { path: path.to_path_buf(), named: true };
+        let mut out = Vec::new();
+        let (new_state, _) = check(config, &file, false, state.take(), &mut out).unwrap();
+        *state = Some(new_state);
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn only_new_and_changed_matches() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("app.log");
+        let config = config(&["-n", "error", "app.log"]);
+        let mut state = None;
+
+        fs::write(&path, "error: one\ninfo\n").unwrap();
+        assert_eq!("1:error: one\n", poll(&config, &path, &mut state));
+        assert_eq!("", poll(&config, &path, &mut state));
+
+        // Appended lines, including one that isn't finished yet and so waits until it is
+        fs::write(&path, "error: one\ninfo\nerror: two\nerror: thr").unwrap();
+        assert_eq!("3:error: two\n", poll(&config, &path, &mut state));
+        fs::write(&path, "error: one\ninfo\nerror: two\nerror: three\n").unwrap();
+        assert_eq!("4:error: three\n", poll(&config, &path, &mut state));
+
+        // Appending doesn't add to what's remembered
+        assert_eq!(1, state.as_ref().unwrap().seen.len());
+
+        // Rewritten: only the line that changed is printed, along with those appended since the
+        // last search from the start
+        fs::write(&path, "error: 1ne\ninfo\nerror: two\n").unwrap();
+        assert_eq!("1:error: 1ne\n3:error: two\n", poll(&config, &path, &mut state));
+        fs::write(&path, "error: 1ne\ninfo\nerror: 2\n").unwrap();
+        assert_eq!("3:error: 2\n", poll(&config, &path, &mut state));
+    }
+
+    #[test]
+    fn binary_files_are_skipped() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data.bin");
+        fs::write(&path, "error\0\n").unwrap();
+        let mut state = None;
+
+        assert_eq!("", poll(&config(&["error", "x"]), &path, &mut state));
+        let mut state = None;
+        assert_eq!("error\0\n", poll(&config(&["-a", "error", "x"]), &path, &mut state));
+    }
+}
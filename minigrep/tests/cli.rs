@@ -0,0 +1,411 @@
+// End to end tests which run the minigrep binary and check its output and exit status
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::process::{Command, Output, Stdio};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+fn minigrep(dir: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_minigrep"))
+        .args(args)
+        .current_dir(dir)
+        .env_remove("CASE_INSENSITIVE")
+        // Keep the configuration files of whoever runs the tests out of them
+        .env("HOME", dir)
+        .env_remove("XDG_CONFIG_HOME")
+        .output()
+        .unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+fn fixture() -> tempfile::TempDir {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("poem.txt"), "I'm nobody! Who are you?\nAre you nobody, too?\n").unwrap();
+    fs::write(dir.path().join("other.txt"), "somebody\n").unwrap();
+    dir
+}
+
+#[test]
+fn exit_status_follows_grep() {
+    let dir = fixture();
+
+    assert_eq!(Some(0), minigrep(dir.path(), &["nobody", "poem.txt"]).status.code());
+    assert_eq!(Some(1), minigrep(dir.path(), &["frog", "poem.txt"]).status.code());
+    assert_eq!(Some(2), minigrep(dir.path(), &["nobody", "missing.txt"]).status.code());
+    assert_eq!(Some(2), minigrep(dir.path(), &["--bogus", "nobody"]).status.code());
+    assert_eq!(Some(0), minigrep(dir.path(), &["--help"]).status.code());
+}
+
+#[cfg(unix)]
+#[test]
+fn unreadable_directories_are_errors() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = fixture();
+    let locked = dir.path().join("locked");
+    fs::create_dir(&locked).unwrap();
+    fs::write(locked.join("poem.txt"), "nobody\n").unwrap();
+    fs::set_permissions(&locked, fs::Permissions::from_mode(0o000)).unwrap();
+    // Permissions don't stop root
+    let readable = fs::read_dir(&locked).is_ok();
+    let output = minigrep(dir.path(), &["nobody", "."]);
+    fs::set_permissions(&locked, fs::Permissions::from_mode(0o755)).unwrap();
+    if readable {
+        return;
+    }
+
+    // The rest of the tree is still searched, but like grep the exit status says what happened
+    let expected = "./poem.txt:I'm nobody! Who are you?\n./poem.txt:Are you nobody, too?\n";
+    assert_eq!(expected, stdout(&output));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("locked"));
+    assert_eq!(Some(2), output.status.code());
+}
+
+#[test]
+fn inverted_match() {
+    let dir = fixture();
+    let output = minigrep(dir.path(), &["-v", "Who", "poem.txt"]);
+
+    assert_eq!("Are you nobody, too?\n", stdout(&output));
+}
+
+#[test]
+fn count_per_file() {
+    let dir = fixture();
+    let output = minigrep(dir.path(), &["-c", "nobody", "poem.txt", "other.txt"]);
+
+    assert_eq!("poem.txt:2\nother.txt:0\n", stdout(&output));
+    assert_eq!(Some(0), output.status.code());
+}
+
+#[test]
+fn files_with_and_without_matches() {
+    let dir = fixture();
+
+    let output = minigrep(dir.path(), &["-l", "nobody", "poem.txt", "other.txt"]);
+    assert_eq!("poem.txt\n", stdout(&output));
+
+    let output = minigrep(dir.path(), &["-L", "nobody", "poem.txt", "other.txt"]);
+    assert_eq!("other.txt\n", stdout(&output));
+    assert_eq!(Some(0), output.status.code());
+
+    let output = minigrep(dir.path(), &["-L", "o", "poem.txt", "other.txt"]);
+    assert_eq!("", stdout(&output));
+    assert_eq!(Some(1), output.status.code());
+}
+
+#[test]
+fn parallel_output_is_deterministic() {
+    let dir = tempfile::tempdir().unwrap();
+    for i in 0..40 {
+        let contents: String = (0..200).map(|line| format!("file {} line {}\n", i, line)).collect();
+        fs::write(dir.path().join(format!("{:02}.txt", i)), contents).unwrap();
+    }
+
+    let sequential = minigrep(dir.path(), &["-j1", "-n", "-C1", "line 1", "."]);
+    let parallel = minigrep(dir.path(), &["-j8", "-n", "-C1", "line 1", "."]);
+
+    assert_eq!(Some(0), parallel.status.code());
+    assert_eq!(stdout(&sequential), stdout(&parallel));
+    assert!(stdout(&parallel).starts_with("./00.txt-1-file 0 line 0\n./00.txt:2:file 0 line 1\n"));
+}
+
+#[test]
+fn json_lines() {
+    let dir = fixture();
+    let output = minigrep(dir.path(), &["--json", "-A1", "Who", "poem.txt", "other.txt"]);
+    let records: Vec<serde_json::Value> = stdout(&output)
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+
+    let kinds: Vec<&str> = records.iter().map(|r| r["type"].as_str().unwrap()).collect();
+    assert_eq!(vec!["begin", "match", "context", "end", "summary"], kinds);
+    assert_eq!("poem.txt", records[1]["data"]["path"]);
+    assert_eq!(1, records[1]["data"]["line_number"]);
+    assert_eq!(12, records[1]["data"]["submatches"][0]["start"]);
+    assert_eq!(25, records[2]["data"]["absolute_offset"]);
+    assert_eq!(2, records[4]["data"]["searches"]);
+    assert_eq!(1, records[4]["data"]["searches_with_match"]);
+    assert_eq!(1, records[4]["data"]["matched_lines"]);
+}
+
+#[test]
+fn binary_files() {
+    let dir = fixture();
+    fs::create_dir(dir.path().join("src")).unwrap();
+    fs::write(dir.path().join("src/image.png"), b"\x89PNG\x00\x01nobody\n").unwrap();
+    fs::write(dir.path().join("src/latin1.txt"), b"caf\xe9 nobody\n").unwrap();
+
+    // Found while walking: skipped, while non-UTF-8 text is still searched
+    let output = minigrep(dir.path(), &["nobody", "src"]);
+    assert_eq!("src/latin1.txt:caf\u{fffd} nobody\n", stdout(&output));
+
+    let output = minigrep(dir.path(), &["nobody", "src/image.png"]);
+    assert_eq!("Binary file src/image.png matches\n", stdout(&output));
+    assert_eq!(Some(0), output.status.code());
+
+    let output = minigrep(dir.path(), &["--binary", "-c", "nobody", "src"]);
+    assert_eq!("src/image.png:1\nsrc/latin1.txt:1\n", stdout(&output));
+
+    let output = minigrep(dir.path(), &["-a", "nobody", "src/image.png"]);
+    assert_eq!("\u{fffd}PNG\0\u{1}nobody\n", stdout(&output));
+}
+
+#[test]
+fn replace_in_place() {
+    let dir = fixture();
+
+    let args = ["--regex", "(\\w+)body", "--replace", "${1}one", "poem.txt"];
+    let output = minigrep(dir.path(), &args);
+    assert_eq!("I'm noone! Who are you?\nAre you noone, too?\n", stdout(&output));
+
+    let output = minigrep(
+        dir.path(),
+        &["--replace", "NOBODY", "--in-place", "--dry-run", "nobody", "poem.txt", "other.txt"],
+    );
+    assert_eq!("\
+--- poem.txt
++++ poem.txt
+@@ -1,2 +1,2 @@
+-I'm nobody! Who are you?
+-Are you nobody, too?
++I'm NOBODY! Who are you?
++Are you NOBODY, too?
+", stdout(&output));
+    assert_eq!(
+        "I'm nobody! Who are you?\nAre you nobody, too?\n",
+        fs::read_to_string(dir.path().join("poem.txt")).unwrap()
+    );
+
+    let output = minigrep(dir.path(), &["--replace", "NOBODY", "--in-place", "nobody", "."]);
+    assert_eq!("", stdout(&output));
+    assert_eq!(Some(0), output.status.code());
+    assert_eq!(
+        "I'm NOBODY! Who are you?\nAre you NOBODY, too?\n",
+        fs::read_to_string(dir.path().join("poem.txt")).unwrap()
+    );
+    assert_eq!("somebody\n", fs::read_to_string(dir.path().join("other.txt")).unwrap());
+}
+
+#[test]
+fn fuzzy_matches_are_ranked() {
+    let dir = fixture();
+    fs::write(dir.path().join("typos.txt"), "nobdy\nnobody\nsomebody\nnbody else\n").unwrap();
+    let output = minigrep(dir.path(), &["-n", "--fuzzy", "nobody", "typos.txt"]);
+
+    assert_eq!("2:~0:nobody\n1:~1:nobdy\n4:~1:nbody else\n", stdout(&output));
+}
+
+#[test]
+fn compressed_files() {
+    use std::io::Write;
+
+    let dir = fixture();
+    let poem = fs::read(dir.path().join("poem.txt")).unwrap();
+    let mut gz = flate2::write::GzEncoder::new(Vec::new(), Default::default());
+    gz.write_all(&poem).unwrap();
+    // A rotated log keeps its compression but not its extension
+    fs::write(dir.path().join("poem.txt.1"), gz.finish().unwrap()).unwrap();
+    let mut xz = Vec::new();
+    lzma_rs::xz_compress(&mut &poem[..], &mut xz).unwrap();
+    fs::write(dir.path().join("poem.txt.xz"), xz).unwrap();
+
+    let output = minigrep(dir.path(), &["-z", "-c", "nobody", "poem.txt.1", "poem.txt.xz"]);
+    assert_eq!("poem.txt.1:2\npoem.txt.xz:2\n", stdout(&output));
+
+    // Without -z they are just binary files with nothing in them to find
+    let output = minigrep(dir.path(), &["nobody", "poem.txt.1"]);
+    assert_eq!("", stdout(&output));
+    assert_eq!(Some(1), output.status.code());
+}
+
+#[test]
+fn index_narrows_the_search() {
+    let dir = fixture();
+    let output = minigrep(dir.path(), &["index", "build"]);
+    assert_eq!("indexed 2 files in . (2 updated, 0 removed)\n", stdout(&output));
+    assert_eq!(Some(0), output.status.code());
+
+    // Sneak a match past the index by keeping the file's size and modification time
+    let other = dir.path().join("other.txt");
+    let modified = fs::metadata(&other).unwrap().modified().unwrap();
+    fs::write(&other, "nobodyyy\n").unwrap();
+    fs::File::options().write(true).open(&other).unwrap().set_modified(modified).unwrap();
+
+    let output = minigrep(dir.path(), &["-l", "nobody", "."]);
+    assert_eq!("./poem.txt\n", stdout(&output));
+    let output = minigrep(dir.path(), &["-l", "--no-index", "nobody", "."]);
+    assert_eq!("./other.txt\n./poem.txt\n", stdout(&output));
+    // Regular expressions can't use the index
+    let output = minigrep(dir.path(), &["-l", "--regex", "no+body", "."]);
+    assert_eq!("./other.txt\n./poem.txt\n", stdout(&output));
+
+    let output = minigrep(dir.path(), &["index", "build"]);
+    assert_eq!("indexed 2 files in . (0 updated, 0 removed)\n", stdout(&output));
+}
+
+#[test]
+fn searching_for_index_in_build() {
+    let dir = fixture();
+    fs::write(dir.path().join("build"), "index me\n").unwrap();
+
+    let output = minigrep(dir.path(), &["--", "index", "build"]);
+    assert_eq!("index me\n", stdout(&output));
+    assert_eq!(Some(0), output.status.code());
+    assert!(!dir.path().join(".minigrep-index").exists());
+}
+
+#[test]
+fn configuration_files() {
+    let dir = fixture();
+    fs::create_dir_all(dir.path().join(".config/minigrep")).unwrap();
+    fs::write(dir.path().join(".config/minigrep/config.toml"), "\
+ignore-case = true
+
+[preset.poems]
+include = [\"poem.*\"]
+line-number = true
+").unwrap();
+    fs::write(dir.path().join(".minigreprc"), "line-number = true\n").unwrap();
+
+    let expected = "./poem.txt:1:I'm nobody! Who are you?\n./poem.txt:2:Are you nobody, too?\n";
+    assert_eq!(expected, stdout(&minigrep(dir.path(), &["NOBODY", "."])));
+    let output = minigrep(dir.path(), &["--preset", "poems", "--count", "NOBODY", "."]);
+    assert_eq!("./poem.txt:2\n", stdout(&output));
+    let output = minigrep(dir.path(), &["--no-config", "NOBODY", "."]);
+    assert_eq!(Some(1), output.status.code());
+
+    fs::write(dir.path().join(".minigreprc"), "line-number = 3\n").unwrap();
+    let output = minigrep(dir.path(), &["nobody", "."]);
+    assert_eq!(Some(2), output.status.code());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains(".minigreprc: 'line-number' should be true or false"), "{}", stderr);
+    // Help is still there to be had
+    assert_eq!(Some(0), minigrep(dir.path(), &["--help"]).status.code());
+    assert_eq!(Some(0), minigrep(dir.path(), &["-V"]).status.code());
+
+    // A configuration file can't make a search write to the files it searches
+    fs::write(dir.path().join(".minigreprc"), "replace = \"PWNED\"\nin-place = true\n").unwrap();
+    let output = minigrep(dir.path(), &["nobody", "poem.txt"]);
+    assert_eq!(Some(2), output.status.code());
+    let poem = fs::read_to_string(dir.path().join("poem.txt")).unwrap();
+    assert_eq!("I'm nobody! Who are you?\nAre you nobody, too?\n", poem);
+}
+
+#[test]
+fn multiline_matches() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("lib.rs"), "fn a(self) {}\nfn b(\n    self,\n) {}\n").unwrap();
+
+    let output = minigrep(dir.path(), &["-U", "-n", "--regex", r"fn \w+\(\s+self", "lib.rs"]);
+    assert_eq!("2:fn b(\n3:    self,\n", stdout(&output));
+    let output = minigrep(dir.path(), &["-U", "-c", "--regex", r"\(\s+self", "lib.rs"]);
+    assert_eq!("2\n", stdout(&output));
+    // Without -U lines are matched one at a time
+    let output = minigrep(dir.path(), &["-n", "--regex", r"fn \w+\(\s+self", "lib.rs"]);
+    assert_eq!(Some(1), output.status.code());
+}
+
+#[test]
+fn code_and_comments() {
+    let dir = tempfile::tempdir().unwrap();
+    let source = "// TODO: parse flags\nfn todo() {\n    /* TODO: tidy\n       todo() */\n    \
+                  println!(\"todo\"); todo()\n}\n";
+    fs::write(dir.path().join("main.rs"), source).unwrap();
+    fs::write(dir.path().join("notes.txt"), "todo\n").unwrap();
+
+    let output = minigrep(dir.path(), &["-i", "-n", "--code-only", "todo", "."]);
+    let expected = "./main.rs:2:fn todo() {\n./main.rs:5:    println!(\"todo\"); todo()\n";
+    assert_eq!(expected, stdout(&output));
+    let output = minigrep(dir.path(), &["-n", "--comments-only", "todo", "main.rs"]);
+    assert_eq!("4:       todo() */\n", stdout(&output));
+    let output = minigrep(dir.path(), &["-c", "--comments-only", "TODO", "main.rs"]);
+    assert_eq!("2\n", stdout(&output));
+}
+
+#[test]
+fn structured_fields() {
+    let dir = tempfile::tempdir().unwrap();
+    let log = "{\"level\":\"info\",\"msg\":\"no error here\"}\n\
+               {\"level\":\"error\",\"msg\":\"disk full\"}\n\
+               {\"level\":\"error\",\"msg\":\n";
+    fs::write(dir.path().join("app.jsonl"), log).unwrap();
+    fs::write(dir.path().join("app.csv"), "when,level\n1,error\n2,warning,error\n3,\"errors, \"\"bad\"\"\"\n").unwrap();
+
+    let output = minigrep(dir.path(), &["-n", "--field", "level=error", "app.jsonl"]);
+    assert_eq!("2:{\"level\":\"error\",\"msg\":\"disk full\"}\n", stdout(&output));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.starts_with("minigrep: app.jsonl:3: invalid JSON record"), "{}", stderr);
+    // Bad records don't make the search fail
+    assert_eq!(Some(0), output.status.code());
+
+    let output = minigrep(dir.path(), &["--column", "--csv-column", "2", "error", "app.csv"]);
+    // A quoted field with escapes in it is pointed at as a whole
+    assert_eq!("3:1,error\n4:3,\"errors, \"\"bad\"\"\"\n", stdout(&output));
+
+    // Values are unescaped before they are matched, so an index of the raw lines can't help
+    fs::write(dir.path().join("cafe.jsonl"), "{\"msg\":\"caf\\u00e9 open\"}\n").unwrap();
+    minigrep(dir.path(), &["index", "build"]);
+    let output = minigrep(dir.path(), &["-l", "--field", "msg=café", "."]);
+    assert_eq!("./cafe.jsonl\n", stdout(&output));
+}
+
+#[test]
+fn limits_sorting_and_unique_lines() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("b.log"), "error\nok\nerror\nerror\nwarn\nok\n").unwrap();
+    fs::write(dir.path().join("a.log"), "error\n").unwrap();
+    // Make a.log the newest
+    let a = fs::File::options().write(true).open(dir.path().join("a.log")).unwrap();
+    a.set_modified(SystemTime::now() + Duration::from_secs(60)).unwrap();
+
+    let output = minigrep(dir.path(), &["-n", "-m2", "-A1", "error", "b.log"]);
+    assert_eq!("1:error\n2-ok\n3:error\n4-error\n", stdout(&output));
+    let output = minigrep(dir.path(), &["-c", "-m", "2", "error", "b.log"]);
+    assert_eq!("2\n", stdout(&output));
+    // With -U it's matches that are counted, and the last one isn't cut short
+    let output = minigrep(dir.path(), &["-n", "-U", "-m1", "--regex", r"error\nok", "b.log"]);
+    assert_eq!("1:error\n2:ok\n", stdout(&output));
+
+    let output = minigrep(dir.path(), &["--sort", "mtime", "-c", "error", "a.log", "b.log"]);
+    assert_eq!("b.log:3\na.log:1\n", stdout(&output));
+    let output = minigrep(dir.path(), &["--sort", "path", "-c", "error", "b.log", "a.log"]);
+    assert_eq!("a.log:1\nb.log:3\n", stdout(&output));
+
+    let output = minigrep(dir.path(), &["-n", "--unique", "--regex", "error|ok", "b.log"]);
+    assert_eq!("1:3x:error\n2:2x:ok\n", stdout(&output));
+}
+
+#[test]
+fn watch_prints_new_matches() {
+    let dir = fixture();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_minigrep"))
+        .args(["--watch", "--no-config", "nobody", "poem.txt"])
+        .current_dir(dir.path())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let (lines, received) = mpsc::channel();
+    let stdout = child.stdout.take().unwrap();
+    thread::spawn(move || {
+        for line in BufReader::new(stdout).lines() {
+            let _ = lines.send(line.unwrap());
+        }
+    });
+    let next = || received.recv_timeout(Duration::from_secs(10)).unwrap();
+
+    assert_eq!("I'm nobody! Who are you?", next());
+    assert_eq!("Are you nobody, too?", next());
+    let mut poem = fs::OpenOptions::new().append(true).open(dir.path().join("poem.txt")).unwrap();
+    poem.write_all(b"How dreary to be somebody!\nNobody knows nobody\n").unwrap();
+    assert_eq!("Nobody knows nobody", next());
+
+    child.kill().unwrap();
+    child.wait().unwrap();
+}